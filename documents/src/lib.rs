@@ -0,0 +1,511 @@
+#[macro_use]
+extern crate serde_derive;
+
+use ini::Ini;
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error, fmt,
+    path::Path,
+};
+use tera::{Context, Tera};
+
+/// The structured formats files can be parsed from and served as.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Ini,
+}
+
+impl Format {
+    /// Tells the format of a file from its extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "ini" => Some(Format::Ini),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Yaml => "application/yaml",
+            Format::Toml => "application/toml",
+            Format::Ini => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+            Format::Ini => "ini",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The document is not valid in the format it was parsed as.
+    Parse { format: Format, message: String },
+    /// The document can't be represented in the format it was serialised as, ie. TOML has no null.
+    Serialise { format: Format, message: String },
+    /// The template is not valid or failed to render with the given context.
+    Render { message: String },
+    /// The JSON schema is not valid itself.
+    Schema { message: String },
+    /// The document doesn't conform to the schema, with a description of each violation.
+    Invalid { violations: Vec<String> },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse { format, message } => {
+                write!(f, "Invalid {} document: {}", format, message)
+            }
+            Error::Serialise { format, message } => {
+                write!(f, "Can't serialise the document as {}: {}", format, message)
+            }
+            Error::Render { message } => write!(f, "Can't render the template: {}", message),
+            Error::Schema { message } => write!(f, "Invalid schema: {}", message),
+            Error::Invalid { violations } => {
+                write!(
+                    f,
+                    "Document doesn't match the schema: {}",
+                    violations.join("; ")
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Parses a document into a format agnostic tree. INI files become an object with the properties
+/// outside of any section at the top and an object per section, all of their values being strings.
+pub fn parse(bytes: &[u8], format: Format) -> Result<Value, Error> {
+    let parse_error = |message: String| Error::Parse { format, message };
+
+    match format {
+        Format::Json => serde_json::from_slice(bytes).map_err(|err| parse_error(err.to_string())),
+        Format::Yaml => serde_yaml::from_slice(bytes).map_err(|err| parse_error(err.to_string())),
+        Format::Toml => toml::from_slice(bytes).map_err(|err| parse_error(err.to_string())),
+        Format::Ini => {
+            let text = std::str::from_utf8(bytes).map_err(|err| parse_error(err.to_string()))?;
+            let ini = Ini::load_from_str(text).map_err(|err| parse_error(err.to_string()))?;
+            Ok(ini_to_value(&ini))
+        }
+    }
+}
+
+fn ini_to_value(ini: &Ini) -> Value {
+    let mut document = Map::new();
+
+    for (section, properties) in ini {
+        let properties = properties
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::String(value.clone())));
+
+        match section {
+            Some(name) => {
+                document.insert(name.clone(), Value::Object(properties.collect()));
+            }
+            None => document.extend(properties),
+        }
+    }
+
+    Value::Object(document)
+}
+
+/// Serialises a document in the given format. INI is only supported as an input format.
+pub fn serialise(document: &Value, format: Format) -> Result<Vec<u8>, Error> {
+    let serialise_error = |message: String| Error::Serialise { format, message };
+
+    match format {
+        Format::Json => {
+            serde_json::to_vec_pretty(document).map_err(|err| serialise_error(err.to_string()))
+        }
+        Format::Yaml => {
+            serde_yaml::to_vec(document).map_err(|err| serialise_error(err.to_string()))
+        }
+        // Going through `toml::Value` puts the tables after the plain values, as TOML requires.
+        Format::Toml => toml::Value::try_from(document)
+            .and_then(|value| toml::to_vec(&value))
+            .map_err(|err| serialise_error(err.to_string())),
+        Format::Ini => Err(serialise_error(
+            "only supported as an input format".to_string(),
+        )),
+    }
+}
+
+/// Parses a document and serialises it in another format.
+pub fn convert(bytes: &[u8], from: Format, to: Format) -> Result<Vec<u8>, Error> {
+    parse(bytes, from).and_then(|document| serialise(&document, to))
+}
+
+/// The result of merging documents, along with the name of the document each value came from
+/// keyed by its JSON pointer.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Merged {
+    pub value: Value,
+    pub sources: BTreeMap<String, String>,
+}
+
+/// Deep merges documents in order, each one overriding the ones before it. Objects are merged key
+/// by key while any other value, arrays included, replaces the previous one as a whole.
+pub fn merge<'a, I>(layers: I) -> Merged
+where
+    I: IntoIterator<Item = (&'a str, Value)>,
+{
+    let mut merged = Merged {
+        value: Value::Null,
+        sources: BTreeMap::new(),
+    };
+
+    for (name, document) in layers {
+        merge_into(&mut merged.value, document, "", name, &mut merged.sources);
+    }
+
+    merged
+}
+
+fn merge_into(
+    target: &mut Value,
+    overlay: Value,
+    pointer: &str,
+    name: &str,
+    sources: &mut BTreeMap<String, String>,
+) {
+    match (target, overlay) {
+        (Value::Object(target), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let pointer = format!("{}/{}", pointer, escape(&key));
+                match target.get_mut(&key) {
+                    Some(existing) => merge_into(existing, value, &pointer, name, sources),
+                    None => {
+                        record_sources(&value, &pointer, name, sources);
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, overlay) => {
+            let nested = format!("{}/", pointer);
+            sources.retain(|key, _| key != pointer && !key.starts_with(&nested));
+            record_sources(&overlay, pointer, name, sources);
+            *target = overlay;
+        }
+    }
+}
+
+// Records `name` as the source of every value in `value` that is not a non-empty object.
+fn record_sources(
+    value: &Value,
+    pointer: &str,
+    name: &str,
+    sources: &mut BTreeMap<String, String>,
+) {
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (key, value) in object {
+                record_sources(
+                    value,
+                    &format!("{}/{}", pointer, escape(key)),
+                    name,
+                    sources,
+                );
+            }
+        }
+        _ => {
+            sources.insert(pointer.to_string(), name.to_string());
+        }
+    }
+}
+
+// Escapes a key to be used as a JSON pointer token.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Renders a [Tera](https://tera.netlify.app/) template with the values of `context`, which must
+/// be an object. Templates can't read the environment of the server.
+pub fn render(template: &[u8], context: &Value) -> Result<Vec<u8>, Error> {
+    let render_error = |err: &dyn error::Error| Error::Render {
+        message: describe(err),
+    };
+
+    let template = std::str::from_utf8(template).map_err(|err| render_error(&err))?;
+    let context = Context::from_value(context.clone()).map_err(|err| render_error(&err))?;
+
+    let mut tera = Tera::default();
+    tera.register_function("get_env", |_: &HashMap<String, Value>| {
+        Err("get_env is not available".into())
+    });
+    tera.add_raw_template("template", template)
+        .and_then(|_| tera.render("template", &context))
+        .map(String::into_bytes)
+        .map_err(|err| render_error(&err))
+}
+
+/// Validates a document against a [JSON schema](https://json-schema.org/), the draft being the
+/// one the schema declares in `$schema` or the latest one otherwise.
+pub fn validate(document: &Value, schema: &Value) -> Result<(), Error> {
+    let validator = jsonschema::validator_for(schema).map_err(|err| Error::Schema {
+        message: err.to_string(),
+    })?;
+
+    let violations: Vec<String> = validator
+        .iter_errors(document)
+        .map(|err| {
+            format!(
+                "{}: {}",
+                pointer_or_root(&err.instance_path().to_string()),
+                err
+            )
+        })
+        .collect();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Invalid { violations })
+    }
+}
+
+fn pointer_or_root(pointer: &str) -> &str {
+    if pointer.is_empty() {
+        "/"
+    } else {
+        pointer
+    }
+}
+
+// Tera's errors only say which template failed at the top, the actual reason is in their sources.
+fn describe(err: &dyn error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        message = format!("{}: {}", message, err);
+        source = err.source();
+    }
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("a/b.yml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("b.yaml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("b.json")), Some(Format::Json));
+        assert_eq!(Format::from_path(Path::new("b.toml")), Some(Format::Toml));
+        assert_eq!(Format::from_path(Path::new("b.ini")), Some(Format::Ini));
+        assert_eq!(Format::from_path(Path::new("b.txt")), None);
+        assert_eq!(Format::from_path(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn test_parse_each_format() {
+        let expected = json!({"name": "app", "database": {"host": "db", "port": 5432}});
+
+        assert_eq!(
+            parse(
+                b"name: app\ndatabase:\n  host: db\n  port: 5432\n",
+                Format::Yaml
+            ),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            parse(
+                b"name = \"app\"\n[database]\nhost = \"db\"\nport = 5432\n",
+                Format::Toml
+            ),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            parse(
+                br#"{"name":"app","database":{"host":"db","port":5432}}"#,
+                Format::Json
+            ),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn test_parse_ini_as_strings() {
+        assert_eq!(
+            parse(b"name = app\n[database]\nport = 5432\n", Format::Ini),
+            Ok(json!({"name": "app", "database": {"port": "5432"}}))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_document() {
+        match parse(b"name: [", Format::Yaml) {
+            Err(Error::Parse { format, .. }) => assert_eq!(format, Format::Yaml),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_convert_yaml_to_toml_puts_tables_last() {
+        let toml = convert(
+            b"database:\n  host: db\nname: app\n",
+            Format::Yaml,
+            Format::Toml,
+        )
+        .expect("should convert");
+
+        assert_eq!(
+            std::str::from_utf8(&toml).unwrap(),
+            "name = \"app\"\n\n[database]\nhost = \"db\"\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_null_to_toml() {
+        match convert(b"name: ~\n", Format::Yaml, Format::Toml) {
+            Err(Error::Serialise { format, .. }) => assert_eq!(format, Format::Toml),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_serialise_as_ini() {
+        assert!(serialise(&json!({}), Format::Ini).is_err());
+    }
+
+    #[test]
+    fn test_merge_overrides_in_order() {
+        let merged = merge(vec![
+            (
+                "base.yaml",
+                json!({"name": "app", "database": {"host": "db", "port": 5432}, "tags": ["a"]}),
+            ),
+            (
+                "prod.yaml",
+                json!({"database": {"host": "prod-db"}, "tags": ["b", "c"]}),
+            ),
+        ]);
+
+        assert_eq!(
+            merged.value,
+            json!({"name": "app", "database": {"host": "prod-db", "port": 5432}, "tags": ["b", "c"]})
+        );
+        assert_eq!(
+            merged.sources,
+            vec![
+                ("/database/host", "prod.yaml"),
+                ("/database/port", "base.yaml"),
+                ("/name", "base.yaml"),
+                ("/tags", "prod.yaml"),
+            ]
+            .into_iter()
+            .map(|(pointer, name)| (pointer.to_string(), name.to_string()))
+            .collect()
+        );
+    }
+
+    #[test]
+    fn test_merge_replacing_an_object() {
+        let merged = merge(vec![
+            ("base.yaml", json!({"database": {"host": "db"}})),
+            ("local.yaml", json!({"database": "sqlite"})),
+            ("eu.yaml", json!({"a/b": {"c~d": 1}})),
+        ]);
+
+        assert_eq!(
+            merged.value,
+            json!({"database": "sqlite", "a/b": {"c~d": 1}})
+        );
+        assert_eq!(
+            merged.sources.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("/a~1b/c~0d".to_string(), "eu.yaml".to_string()),
+                ("/database".to_string(), "local.yaml".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let rendered = render(
+            b"host: {{ database.host }}\nenv: {{ env | upper }}\n",
+            &json!({"database": {"host": "db"}, "env": "prod"}),
+        )
+        .expect("should render");
+
+        assert_eq!(
+            std::str::from_utf8(&rendered).unwrap(),
+            "host: db\nenv: PROD\n"
+        );
+    }
+
+    #[test]
+    fn test_render_with_missing_variable() {
+        match render(b"{{ nope }}", &json!({})) {
+            Err(Error::Render { message }) => assert!(message.contains("nope"), "{}", message),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_render_without_environment() {
+        assert!(render(b"{{ get_env(name='HOME') }}", &json!({})).is_err());
+    }
+
+    #[test]
+    fn test_validate_valid_document() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"port": {"type": "integer"}},
+            "required": ["port"]
+        });
+
+        assert_eq!(validate(&json!({"port": 5432}), &schema), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_reports_every_violation() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"port": {"type": "integer"}, "host": {"type": "string"}},
+            "required": ["name"]
+        });
+
+        match validate(&json!({"port": "5432", "host": 1}), &schema) {
+            Err(Error::Invalid { mut violations }) => {
+                violations.sort();
+                assert_eq!(violations.len(), 3, "{:?}", violations);
+                assert!(violations[0].starts_with("/: "), "{:?}", violations);
+                assert!(violations[0].contains("name"), "{:?}", violations);
+                assert!(violations[1].starts_with("/host: "), "{:?}", violations);
+                assert!(violations[2].starts_with("/port: "), "{:?}", violations);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validate_with_invalid_schema() {
+        match validate(&json!({}), &json!({"type": "nope"})) {
+            Err(Error::Schema { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
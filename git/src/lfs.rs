@@ -0,0 +1,113 @@
+use git2::Repository;
+use std::path::PathBuf;
+
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+const OID_PREFIX: &str = "sha256:";
+
+/// A [Git LFS pointer](https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md), stored in the
+/// tree in place of the file it points to.
+#[derive(Debug, PartialEq)]
+pub struct LfsPointer {
+    /// The SHA-256 of the file, as hex.
+    pub oid: String,
+    pub size: u64,
+}
+
+impl LfsPointer {
+    /// Pointer files are small text files, anything larger is the content of a regular file.
+    pub const MAX_SIZE: u64 = 1024;
+
+    /// Parses the content of a blob as a pointer, answering `None` when it isn't one.
+    pub fn parse(content: &[u8]) -> Option<LfsPointer> {
+        if content.len() as u64 > LfsPointer::MAX_SIZE {
+            return None;
+        }
+
+        let mut lines = std::str::from_utf8(content).ok()?.lines();
+        if lines.next()? != POINTER_VERSION {
+            return None;
+        }
+
+        let mut oid = None;
+        let mut size = None;
+        for line in lines {
+            match line.split_once(' ')? {
+                ("oid", value) => oid = value.strip_prefix(OID_PREFIX),
+                ("size", value) => size = value.parse().ok(),
+                _ => (),
+            }
+        }
+
+        // The OID becomes a path in the object store, so it has to be exactly a SHA-256.
+        let oid =
+            oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()))?;
+
+        Some(LfsPointer {
+            oid: oid.to_lowercase(),
+            size: size?,
+        })
+    }
+
+    /// Where the file pointed to is kept in the local object store of the repository, which may
+    /// not have it if it was never fetched.
+    pub fn object_path(&self, repo: &Repository) -> PathBuf {
+        repo.path()
+            .join("lfs")
+            .join("objects")
+            .join(&self.oid[0..2])
+            .join(&self.oid[2..4])
+            .join(&self.oid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    fn pointer(oid: &str) -> String {
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+            oid
+        )
+    }
+
+    #[test]
+    fn test_parse_pointer() {
+        assert_eq!(
+            LfsPointer::parse(pointer(OID).as_bytes()),
+            Some(LfsPointer {
+                oid: OID.to_string(),
+                size: 12345
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_regular_file() {
+        assert_eq!(LfsPointer::parse(b"version: 1\nname: app\n"), None);
+        assert_eq!(LfsPointer::parse(&[0xff, 0xfe]), None);
+    }
+
+    #[test]
+    fn test_parse_pointer_with_invalid_oid() {
+        assert_eq!(
+            LfsPointer::parse(pointer("../../../etc/passwd").as_bytes()),
+            None
+        );
+        assert_eq!(LfsPointer::parse(pointer(&OID[1..]).as_bytes()), None);
+    }
+
+    #[test]
+    fn test_object_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        let pointer = LfsPointer::parse(pointer(OID).as_bytes()).unwrap();
+
+        assert_eq!(
+            pointer.object_path(&repo),
+            dir.path().join("lfs/objects/4d/7a").join(OID)
+        );
+    }
+}
@@ -1,23 +1,191 @@
 pub extern crate git2;
 
-use git2::{Error, Repository};
-use std::{
-    collections::HashMap,
-    fs,
-    path::{Path, PathBuf},
+mod lfs;
+mod protection;
+mod repos;
+mod signatures;
+mod submodules;
+
+pub use lfs::LfsPointer;
+pub use protection::{ProtectionRules, RefUpdate, FAST_FORWARD_ONLY_KEY, PROTECTED_REF_KEY};
+pub use repos::{
+    load_repos, repo_key, LoadError, LoadedRepos, RepoDiagnostic, RepoStatus, NAMESPACE_SEPARATOR,
+};
+pub use signatures::{Keyring, SignatureStatus, SigningKey};
+pub use submodules::{normalise_url, SubmoduleEntry};
+
+use git2::{
+    BlameOptions, Error, ErrorClass, ErrorCode, ObjectType, Oid, Reference, ReferenceType,
+    Repository, Signature, Tree, TreeWalkMode, TreeWalkResult,
 };
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::{info_span, instrument};
+
+// The git file mode of symlinks, whose content is the path they link to.
+const SYMLINK_MODE: i32 = 0o120_000;
+// As many as Linux follows before giving up, so that symlinks linking to each other fail instead
+// of looping forever.
+const MAX_SYMLINK_HOPS: usize = 40;
 
 pub trait GitOps {
-    fn cat_file(&self, repo: &Repository, reference: &str, path: &Path) -> Result<Vec<u8>, Error>;
+    fn cat_file(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<Vec<u8>, Error>;
+
+    fn find_blob(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<BlobHeader, Error>;
+
+    fn read_blob(&self, repo: &Repository, id: Oid) -> Result<Vec<u8>, Error>;
 
     fn ls_dir(
         &self,
         repo: &Repository,
         reference: &str,
         path: &Path,
+        follow_symlinks: bool,
     ) -> Result<Vec<PathBuf>, Error>;
 
+    fn read_tree(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+    ) -> Result<Vec<TreeFile>, Error>;
+
+    fn find_submodule(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+    ) -> Result<Option<SubmoduleEntry>, Error>;
+
+    fn blame_file(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+    ) -> Result<Vec<BlameHunk>, Error>;
+
+    fn list_refs(&self, repo: &Repository) -> Result<Vec<RefEntry>, Error>;
+
+    fn create_tag(
+        &self,
+        repo: &Repository,
+        name: &str,
+        target: &str,
+        annotation: Option<(&Signature, &str)>,
+        signing_key: Option<&SigningKey>,
+    ) -> Result<RefEntry, Error>;
+
+    fn create_branch(&self, repo: &Repository, name: &str, target: &str)
+        -> Result<RefEntry, Error>;
+
+    fn move_branch(
+        &self,
+        repo: &Repository,
+        name: &str,
+        target: &str,
+        old_target: &str,
+        force: bool,
+    ) -> Result<RefEntry, Error>;
+
+    fn delete_ref(
+        &self,
+        repo: &Repository,
+        kind: RefKind,
+        name: &str,
+        old_target: &str,
+    ) -> Result<(), Error>;
+
     fn resolve_ref(&self, repo: &Repository, reference: &str) -> Result<String, Error>;
+
+    fn resolve_ref_at(&self, repo: &Repository, reference: &str, at: i64) -> Result<String, Error>;
+
+    fn verify_signature(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        commit: &str,
+        keyring: &Keyring,
+    ) -> Result<SignatureStatus, Error>;
+}
+
+/// A blob found in a tree, its size being read from the header of the object so that it's known
+/// before its content is read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlobHeader {
+    pub id: Oid,
+    pub size: u64,
+}
+
+/// A file found under a directory of a tree. Its content is left to be read when needed, so that
+/// listing a large tree doesn't load all of it.
+#[derive(Debug, PartialEq)]
+pub struct TreeFile {
+    /// Relative to the directory the tree was read from.
+    pub path: PathBuf,
+    /// The git file mode, ie. `0o100755` for an executable file or `0o120000` for a symlink,
+    /// whose content is the path it links to.
+    pub mode: i32,
+    /// The size of its content, in bytes.
+    pub size: u64,
+}
+
+/// Consecutive lines of a file last changed by the same commit.
+#[derive(Debug, PartialEq)]
+pub struct BlameHunk {
+    /// The first line, counting from 1.
+    pub start_line: usize,
+    pub lines: usize,
+    /// The SHA of the commit that last changed the lines.
+    pub commit: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// When the commit was authored, in seconds since the Unix epoch.
+    pub timestamp: i64,
+}
+
+/// A branch, tag or remote-tracking branch of a repository.
+#[derive(Debug, PartialEq)]
+pub struct RefEntry {
+    /// The short name of the reference, ie. `master`, `v1.0` or `origin/master`, which is what
+    /// reads take as their reference.
+    pub name: String,
+    pub kind: RefKind,
+    /// The SHA of the object the reference points to, which for annotated tags is the object they
+    /// tag rather than the tag itself.
+    pub target: String,
+    /// What annotated tags say about themselves, `None` for any other reference.
+    pub tag: Option<AnnotatedTag>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum RefKind {
+    Branch,
+    Remote,
+    Tag,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AnnotatedTag {
+    /// The SHA of the tag itself, which is what the reference points to.
+    pub id: String,
+    pub tagger_name: Option<String>,
+    pub tagger_email: Option<String>,
+    /// When the tag was created, in seconds since the Unix epoch.
+    pub timestamp: Option<i64>,
+    pub message: Option<String>,
 }
 
 pub struct LibGitOps;
@@ -25,71 +193,684 @@ pub struct LibGitOps;
 impl GitOps for LibGitOps {
     /// Given an existing git repository, it will read the blob that the reference and the filename
     /// point to and return it as a String.
-    fn cat_file(&self, repo: &Repository, reference: &str, path: &Path) -> Result<Vec<u8>, Error> {
-        let git_ref = repo.revparse_single(reference)?;
-        let tree = git_ref.peel_to_tree()?;
-        let te = tree.get_path(path)?;
+    #[instrument(skip(self, repo))]
+    fn cat_file(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<Vec<u8>, Error> {
+        let blob = self.find_blob(repo, reference, path, follow_symlinks)?;
+        self.read_blob(repo, blob.id)
+    }
+
+    /// Finds the blob that the reference and the filename point to without reading its content,
+    /// ie. to tell its size before reading it whole or in part. Symlinks are read as the path
+    /// they link to unless asked to follow them.
+    #[instrument(skip(self, repo))]
+    fn find_blob(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<BlobHeader, Error> {
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(reference))?;
+        let tree = info_span!("peel_to_tree").in_scope(|| git_ref.peel_to_tree())?;
+        let path = if follow_symlinks {
+            info_span!("follow_symlinks").in_scope(|| follow(repo, &tree, path))?
+        } else {
+            path.to_path_buf()
+        };
+        let te = info_span!("tree_lookup").in_scope(|| tree.get_path(&path))?;
 
-        repo.find_blob(te.id()).map(|x| x.content().to_owned())
+        let (size, kind) =
+            info_span!("read_header").in_scope(|| repo.odb()?.read_header(te.id()))?;
+        if kind != ObjectType::Blob {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Invalid,
+                format!("'{}' is not a file", path.display()),
+            ));
+        }
+
+        Ok(BlobHeader {
+            id: te.id(),
+            size: size as u64,
+        })
+    }
+
+    #[instrument(skip(self, repo))]
+    fn read_blob(&self, repo: &Repository, id: Oid) -> Result<Vec<u8>, Error> {
+        info_span!("find_blob").in_scope(|| repo.find_blob(id).map(|blob| blob.content().to_vec()))
     }
 
+    #[instrument(skip(self, repo))]
     fn ls_dir(
         &self,
         repo: &Repository,
         reference: &str,
         directory: &Path,
+        follow_symlinks: bool,
     ) -> Result<Vec<PathBuf>, Error> {
-        let git_ref = repo.revparse_single(reference)?;
-        let tree = git_ref.peel_to_tree()?;
-        let path = std::path::Path::new(directory);
-        let te = tree.get_path(path)?;
-
-        repo.find_tree(te.id()).map({
-            |tree| {
-                tree.iter()
-                    .flat_map(|tree_entry| tree_entry.name().map(|name| name.into()))
-                    .collect()
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(reference))?;
+        let tree = info_span!("peel_to_tree").in_scope(|| git_ref.peel_to_tree())?;
+        let path = if follow_symlinks {
+            info_span!("follow_symlinks").in_scope(|| follow(repo, &tree, directory))?
+        } else {
+            directory.to_path_buf()
+        };
+
+        // An empty path lists the root of the tree, ie. the root of a submodule.
+        let tree = if path.as_os_str().is_empty() {
+            tree
+        } else {
+            let te = info_span!("tree_lookup").in_scope(|| tree.get_path(&path))?;
+            info_span!("find_tree").in_scope(|| repo.find_tree(te.id()))?
+        };
+
+        Ok(tree
+            .iter()
+            .flat_map(|tree_entry| tree_entry.name().map(|name| name.into()))
+            .collect())
+    }
+
+    /// Lists every file under a directory, in the order they appear in the tree. Submodules are
+    /// left out as their content isn't part of the repository.
+    #[instrument(skip(self, repo))]
+    fn read_tree(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        directory: &Path,
+    ) -> Result<Vec<TreeFile>, Error> {
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(reference))?;
+        let tree = info_span!("peel_to_tree").in_scope(|| git_ref.peel_to_tree())?;
+        let te = info_span!("tree_lookup").in_scope(|| tree.get_path(directory))?;
+        let subtree = info_span!("find_tree").in_scope(|| repo.find_tree(te.id()))?;
+
+        let odb = repo.odb()?;
+        let mut files = Vec::new();
+        let mut failure = None;
+        info_span!("walk_tree").in_scope(|| {
+            subtree.walk(TreeWalkMode::PreOrder, |root, entry| {
+                if entry.kind() != Some(ObjectType::Blob) {
+                    return TreeWalkResult::Ok;
+                }
+
+                // Only the header of the blob is read, which tells its size without its content.
+                match odb.read_header(entry.id()) {
+                    Ok((size, _)) => {
+                        files.push(TreeFile {
+                            path: Path::new(root).join(entry.name().unwrap_or_default()),
+                            mode: entry.filemode(),
+                            size: size as u64,
+                        });
+                        TreeWalkResult::Ok
+                    }
+                    Err(err) => {
+                        failure = Some(err);
+                        TreeWalkResult::Abort
+                    }
+                }
+            })
+        })?;
+
+        failure.map_or(Ok(files), Err)
+    }
+
+    /// Finds the first submodule along the path, if any. The path doesn't need to exist past the
+    /// submodule, as that's for the repository of the submodule to tell.
+    #[instrument(skip(self, repo))]
+    fn find_submodule(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+    ) -> Result<Option<SubmoduleEntry>, Error> {
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(reference))?;
+        let tree = info_span!("peel_to_tree").in_scope(|| git_ref.peel_to_tree())?;
+
+        let mut prefix = PathBuf::new();
+        for component in path.components() {
+            prefix.push(component);
+
+            let te = match info_span!("tree_lookup").in_scope(|| tree.get_path(&prefix)) {
+                Ok(te) => te,
+                // Leave it to the lookup of the whole path to report it missing.
+                Err(_) => return Ok(None),
+            };
+
+            match te.kind() {
+                Some(ObjectType::Tree) => continue,
+                Some(ObjectType::Commit) => (),
+                _ => return Ok(None),
             }
-        })
+
+            let url = match tree.get_path(Path::new(".gitmodules")) {
+                Ok(gitmodules) => {
+                    let blob = repo.find_blob(gitmodules.id())?;
+                    std::str::from_utf8(blob.content())
+                        .ok()
+                        .and_then(|gitmodules| submodules::submodule_url(gitmodules, &prefix))
+                }
+                Err(_) => None,
+            };
+
+            return Ok(Some(SubmoduleEntry {
+                rest: path.strip_prefix(&prefix).unwrap_or(path).to_path_buf(),
+                path: prefix,
+                url,
+                commit: te.id().to_string(),
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// Tells the commit that last changed each line of a file, looking at the history of the commit
+    /// the reference points to.
+    #[instrument(skip(self, repo))]
+    fn blame_file(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        path: &Path,
+    ) -> Result<Vec<BlameHunk>, Error> {
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(reference))?;
+        let commit = info_span!("peel_to_commit").in_scope(|| git_ref.peel_to_commit())?;
+        // Blame walks the history without checking the path is a file, so check it beforehand.
+        let te = info_span!("tree_lookup").in_scope(|| commit.tree()?.get_path(path))?;
+        info_span!("find_blob").in_scope(|| repo.find_blob(te.id()))?;
+
+        let blame = info_span!("blame").in_scope(|| {
+            repo.blame_file(path, Some(BlameOptions::new().newest_commit(commit.id())))
+        })?;
+
+        Ok(blame
+            .iter()
+            .map(|hunk| {
+                let author = hunk.final_signature();
+                BlameHunk {
+                    start_line: hunk.final_start_line(),
+                    lines: hunk.lines_in_hunk(),
+                    commit: hunk.final_commit_id().to_string(),
+                    author_name: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+                    author_email: String::from_utf8_lossy(author.email_bytes()).into_owned(),
+                    timestamp: author.when().seconds(),
+                }
+            })
+            .collect())
+    }
+
+    /// Lists the branches, tags and remote-tracking branches of the repository by kind and name.
+    /// Symbolic references, ie. `origin/HEAD`, are left out as they are aliases of another one.
+    #[instrument(skip(self, repo))]
+    fn list_refs(&self, repo: &Repository) -> Result<Vec<RefEntry>, Error> {
+        let references = info_span!("references").in_scope(|| repo.references())?;
+
+        let mut refs = Vec::new();
+        for reference in references {
+            let reference = reference?;
+            let kind = if reference.is_branch() {
+                RefKind::Branch
+            } else if reference.is_remote() {
+                RefKind::Remote
+            } else if reference.is_tag() {
+                RefKind::Tag
+            } else {
+                continue;
+            };
+
+            if reference.kind() == Some(ReferenceType::Direct) {
+                refs.push(ref_entry(repo, &reference, kind)?);
+            }
+        }
+
+        refs.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+        Ok(refs)
+    }
+
+    /// Tags the commit the target resolves to, with an annotated tag when given who tags it and why
+    /// or with a lightweight one otherwise. Annotated tags are signed when given a key to sign
+    /// them with, and lightweight ones are refused then, as they can't be. Existing tags are never
+    /// replaced.
+    #[instrument(skip(self, repo, annotation, signing_key))]
+    fn create_tag(
+        &self,
+        repo: &Repository,
+        name: &str,
+        target: &str,
+        annotation: Option<(&Signature, &str)>,
+        signing_key: Option<&SigningKey>,
+    ) -> Result<RefEntry, Error> {
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(target))?;
+        let commit = info_span!("peel_to_commit").in_scope(|| git_ref.peel_to_commit())?;
+
+        info_span!("tag").in_scope(|| match (annotation, signing_key) {
+            (Some((tagger, message)), Some(key)) => {
+                signed_tag(repo, name, commit.id(), tagger, message, key)
+            }
+            (Some((tagger, message)), None) => {
+                repo.tag(name, commit.as_object(), tagger, message, false)
+            }
+            (None, None) => repo.tag_lightweight(name, commit.as_object(), false),
+            (None, Some(_)) => Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Tag,
+                format!(
+                    "'{}' can't be signed without a message, as it would be a lightweight tag",
+                    name
+                ),
+            )),
+        })?;
+
+        let reference = repo.find_reference(&format!("refs/tags/{}", name))?;
+        ref_entry(repo, &reference, RefKind::Tag)
+    }
+
+    /// Creates a branch at the commit the target resolves to. Existing branches are never replaced.
+    #[instrument(skip(self, repo))]
+    fn create_branch(
+        &self,
+        repo: &Repository,
+        name: &str,
+        target: &str,
+    ) -> Result<RefEntry, Error> {
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(target))?;
+        let commit = info_span!("peel_to_commit").in_scope(|| git_ref.peel_to_commit())?;
+
+        let branch = info_span!("branch").in_scope(|| repo.branch(name, &commit, false))?;
+        ref_entry(repo, branch.get(), RefKind::Branch)
+    }
+
+    /// Moves a branch to the commit the target resolves to, as long as the branch is still at
+    /// `old_target`, a full SHA. Fails with `Modified` when it isn't, even if it moved while being
+    /// moved, and with `NotFastForward` when the target doesn't descend from it unless forced to.
+    /// Forcing it fails with `Locked` when the protection rules of the repo don't allow it.
+    #[instrument(skip(self, repo))]
+    fn move_branch(
+        &self,
+        repo: &Repository,
+        name: &str,
+        target: &str,
+        old_target: &str,
+        force: bool,
+    ) -> Result<RefEntry, Error> {
+        let old = parse_full_sha(old_target)?;
+        let refname = format!("refs/heads/{}", name);
+        let current = info_span!("find_reference").in_scope(|| repo.find_reference(&refname))?;
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(target))?;
+        let new = info_span!("peel_to_commit")
+            .in_scope(|| git_ref.peel_to_commit())?
+            .id();
+
+        let current = current.target().unwrap_or_else(Oid::zero);
+        if current != old {
+            return Err(Error::new(
+                ErrorCode::Modified,
+                ErrorClass::Reference,
+                format!("'{}' is at {}, not at {}", name, current, old),
+            ));
+        }
+
+        let fast_forward = new == current
+            || info_span!("descendant_of").in_scope(|| repo.graph_descendant_of(new, current))?;
+        if !fast_forward && !force {
+            return Err(Error::new(
+                ErrorCode::NotFastForward,
+                ErrorClass::Reference,
+                format!(
+                    "{} doesn't descend from {}, the commit of '{}'",
+                    new, current, name
+                ),
+            ));
+        }
+        if !fast_forward {
+            ProtectionRules::load(repo)?.check(&refname, RefUpdate::ForceMove)?;
+        }
+
+        // Only updated if still at the commit checked above, in case it moved since.
+        let reference = info_span!("update_reference").in_scope(|| {
+            let message = if fast_forward {
+                format!("gitkv: fast-forward to {}", new)
+            } else {
+                format!("gitkv: forced update to {}", new)
+            };
+            repo.reference_matching(&refname, new, true, current, &message)
+        })?;
+        ref_entry(repo, &reference, RefKind::Branch)
     }
 
+    /// Deletes a branch or tag, as long as it's still at `old_target`, a full SHA, which for
+    /// annotated tags is the SHA of the tag itself. Fails with `Locked` when the protection rules of
+    /// the repo don't allow it.
+    #[instrument(skip(self, repo))]
+    fn delete_ref(
+        &self,
+        repo: &Repository,
+        kind: RefKind,
+        name: &str,
+        old_target: &str,
+    ) -> Result<(), Error> {
+        let old = parse_full_sha(old_target)?;
+        let refname = match kind {
+            RefKind::Branch => format!("refs/heads/{}", name),
+            RefKind::Remote => format!("refs/remotes/{}", name),
+            RefKind::Tag => format!("refs/tags/{}", name),
+        };
+
+        let mut reference =
+            info_span!("find_reference").in_scope(|| repo.find_reference(&refname))?;
+        ProtectionRules::load(repo)?.check(&refname, RefUpdate::Delete)?;
+
+        let current = reference.target().unwrap_or_else(Oid::zero);
+        if current != old {
+            return Err(Error::new(
+                ErrorCode::Modified,
+                ErrorClass::Reference,
+                format!("'{}' is at {}, not at {}", name, current, old),
+            ));
+        }
+
+        // Fails if the reference moved since it was found above.
+        info_span!("delete_reference").in_scope(|| reference.delete())
+    }
+
+    #[instrument(skip(self, repo))]
     fn resolve_ref(&self, repo: &Repository, reference: &str) -> Result<String, Error> {
-        let git_ref = repo.revparse_single(reference)?;
-        git_ref.peel_to_commit().map(|c| c.id().to_string())
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(reference))?;
+        info_span!("peel_to_commit")
+            .in_scope(|| git_ref.peel_to_commit().map(|c| c.id().to_string()))
     }
-}
 
-pub fn load_repos(root_path: &Path) -> HashMap<String, Repository> {
-    fs::read_dir(root_path)
-        .expect("Failed to read repos directory")
-        .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                let path = e.path();
-                if path.is_dir() {
-                    let local_path = path.clone();
-                    let repo_name = local_path
-                        .file_stem()
-                        .and_then(|name| name.to_os_string().into_string().ok());
-
-                    repo_name.and_then(|name| Repository::open(path).ok().map(|repo| (name, repo)))
-                } else {
-                    None
+    /// Resolves the reference to the last commit it had at or before the given time, in seconds
+    /// since the Unix epoch. Only the first parent of merges is followed, so that the commits of a
+    /// merged branch don't count as being on the reference before the time they were merged at.
+    #[instrument(skip(self, repo))]
+    fn resolve_ref_at(&self, repo: &Repository, reference: &str, at: i64) -> Result<String, Error> {
+        let git_ref = info_span!("revparse").in_scope(|| repo.revparse_single(reference))?;
+        let mut commit = info_span!("peel_to_commit").in_scope(|| git_ref.peel_to_commit())?;
+
+        let _span = info_span!("walk_first_parents").entered();
+        // Commit times are when they were committed, which for the first parents of a branch is
+        // when they were on it.
+        while commit.time().seconds() > at {
+            commit = match commit.parent(0) {
+                Ok(parent) => parent,
+                Err(err) if err.code() == ErrorCode::NotFound => {
+                    return Err(Error::new(
+                        ErrorCode::NotFound,
+                        ErrorClass::Reference,
+                        format!("'{}' has no commit at or before {}", reference, at),
+                    ))
                 }
-            })
+                Err(err) => return Err(err),
+            };
+        }
+
+        Ok(commit.id().to_string())
+    }
+
+    /// Tells whether the commit the reference resolved to is signed by a key of the keyring. When
+    /// the reference is an annotated tag of that commit, a signature of the tag vouches for it too.
+    #[instrument(skip(self, repo, keyring))]
+    fn verify_signature(
+        &self,
+        repo: &Repository,
+        reference: &str,
+        commit: &str,
+        keyring: &Keyring,
+    ) -> Result<SignatureStatus, Error> {
+        let commit = Oid::from_str(commit)?;
+        let status = keyring.verify_commit(repo, commit)?;
+        if let SignatureStatus::Verified { .. } = status {
+            return Ok(status);
+        }
+
+        let tag = repo
+            .revparse_single(reference)
+            .ok()
+            .and_then(|object| object.into_tag().ok())
+            .filter(|tag| {
+                tag.as_object()
+                    .peel_to_commit()
+                    .is_ok_and(|tagged| tagged.id() == commit)
+            });
+        let tag_status = match tag {
+            Some(tag) => keyring.verify_tag(repo, tag.id())?,
+            None => SignatureStatus::Unsigned,
+        };
+
+        Ok(match (status, tag_status) {
+            (_, verified @ SignatureStatus::Verified { .. }) => verified,
+            (SignatureStatus::Unsigned, tag_status) => tag_status,
+            (status, _) => status,
+        })
+    }
+}
+
+// Writes an annotated tag of the commit the way git does when signing it, with the signature
+// appended to its message, since libgit2 can't create signed tags itself.
+fn signed_tag(
+    repo: &Repository,
+    name: &str,
+    commit: Oid,
+    tagger: &Signature,
+    message: &str,
+    signing_key: &SigningKey,
+) -> Result<Oid, Error> {
+    let refname = format!("refs/tags/{}", name);
+    if !Reference::is_valid_name(&refname) {
+        return Err(Error::new(
+            ErrorCode::InvalidSpec,
+            ErrorClass::Tag,
+            format!("'{}' is not a valid tag name", name),
+        ));
+    }
+    // Checked before signing, so that no tag object is left behind when the tag exists.
+    if repo.find_reference(&refname).is_ok() {
+        return Err(Error::new(
+            ErrorCode::Exists,
+            ErrorClass::Tag,
+            format!("Tag '{}' already exists", name),
+        ));
+    }
+
+    let offset = tagger.when().offset_minutes();
+    let mut content = format!("object {}\ntype commit\ntag {}\ntagger ", commit, name).into_bytes();
+    content.extend_from_slice(tagger.name_bytes());
+    content.extend_from_slice(b" <");
+    content.extend_from_slice(tagger.email_bytes());
+    content.extend_from_slice(
+        format!(
+            "> {} {}{:02}{:02}\n\n{}",
+            tagger.when().seconds(),
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 60,
+            offset.abs() % 60,
+            message
+        )
+        .as_bytes(),
+    );
+    // The signature starts on a line of its own.
+    if !content.ends_with(b"\n") {
+        content.push(b'\n');
+    }
+
+    let signature = info_span!("sign").in_scope(|| signing_key.sign(&content))?;
+    content.extend_from_slice(signature.as_bytes());
+    let tag = repo.odb()?.write(ObjectType::Tag, &content)?;
+    repo.reference(&refname, tag, false, &format!("tag: {}", name))?;
+
+    Ok(tag)
+}
+
+/// Writes `length` bytes of a blob from `first`. Loose objects are inflated a bit at a time as
+/// they are written, but libgit2 can only read packed ones whole.
+pub fn write_blob<W: Write + ?Sized>(
+    repo: &Repository,
+    id: Oid,
+    first: u64,
+    length: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    let odb = repo.odb().map_err(io::Error::other)?;
+
+    let copied = match odb.reader(id) {
+        Ok((reader, size, _)) => {
+            // the stream of a loose object doesn't end with it, only its header tells its size
+            let mut reader = reader.take(size as u64);
+            io::copy(&mut (&mut reader).take(first), &mut io::sink())?;
+            io::copy(&mut reader.take(length), writer)?
+        }
+        Err(_) => {
+            let object = odb.read(id).map_err(io::Error::other)?;
+            let data = object.data();
+            let start = data.len().min(first as usize);
+            let part = &data[start..data.len().min(start + length as usize)];
+            writer.write_all(part)?;
+            part.len() as u64
+        }
+    };
+
+    if copied < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn parse_full_sha(sha: &str) -> Result<Oid, Error> {
+    match Oid::from_str(sha) {
+        Ok(oid) if sha.len() == 40 => Ok(oid),
+        _ => Err(Error::new(
+            ErrorCode::InvalidSpec,
+            ErrorClass::Invalid,
+            format!("'{}' is not the full SHA of a commit", sha),
+        )),
+    }
+}
+
+// Describes a direct reference, telling annotated tags apart by what they point to.
+fn ref_entry(repo: &Repository, reference: &Reference, kind: RefKind) -> Result<RefEntry, Error> {
+    let tag = reference
+        .target()
+        .and_then(|id| repo.find_tag(id).ok())
+        .map(|tag| {
+            let tagger = tag.tagger();
+            AnnotatedTag {
+                id: tag.id().to_string(),
+                tagger_name: tagger
+                    .as_ref()
+                    .map(|tagger| String::from_utf8_lossy(tagger.name_bytes()).into_owned()),
+                tagger_email: tagger
+                    .as_ref()
+                    .map(|tagger| String::from_utf8_lossy(tagger.email_bytes()).into_owned()),
+                timestamp: tagger.as_ref().map(|tagger| tagger.when().seconds()),
+                message: tag
+                    .message_bytes()
+                    .map(|message| String::from_utf8_lossy(message).into_owned()),
+            }
+        });
+
+    Ok(RefEntry {
+        name: String::from_utf8_lossy(reference.shorthand_bytes()).into_owned(),
+        kind,
+        target: reference.peel(ObjectType::Any)?.id().to_string(),
+        tag,
+    })
+}
+
+// Resolves the symlinks along a path to the path they lead to in the tree, relative to the
+// directory each of them is in. Symlinks may only lead to other paths in the tree, so absolute ones
+// and ones going above the root are refused, like symlinks linking to each other in a loop.
+fn follow(repo: &Repository, tree: &Tree, path: &Path) -> Result<PathBuf, Error> {
+    let mut resolved = PathBuf::new();
+    let mut hops = 0;
+    // The components left to resolve, last one first.
+    let mut pending = components(path)
+        .ok_or_else(|| escaping(path))?
+        .into_iter()
+        .rev()
+        .collect::<Vec<_>>();
+
+    while let Some(component) = pending.pop() {
+        if component == ".." {
+            if !resolved.pop() {
+                return Err(escaping(path));
+            }
+            continue;
+        }
+
+        let candidate = resolved.join(&component);
+        let te = tree.get_path(&candidate)?;
+        if te.filemode() != SYMLINK_MODE {
+            resolved = candidate;
+            continue;
+        }
+
+        hops += 1;
+        if hops > MAX_SYMLINK_HOPS {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Tree,
+                format!("too many levels of symlinks in '{}'", path.display()),
+            ));
+        }
+
+        let blob = repo.find_blob(te.id())?;
+        let target = Path::new(std::str::from_utf8(blob.content()).map_err(|_| {
+            Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Tree,
+                format!("the symlink '{}' isn't valid UTF-8", candidate.display()),
+            )
+        })?);
+        pending.extend(
+            components(target)
+                .ok_or_else(|| escaping(path))?
+                .into_iter()
+                .rev(),
+        );
+    }
+
+    Ok(resolved)
+}
+
+// The names along a relative path, `..` included, or `None` for an absolute path.
+fn components(path: &Path) -> Option<Vec<OsString>> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(Some(name.to_owned())),
+            Component::ParentDir => Some(Some(OsString::from(".."))),
+            Component::CurDir => None,
+            Component::RootDir | Component::Prefix(_) => Some(None),
         })
         .collect()
 }
 
+fn escaping(path: &Path) -> Error {
+    Error::new(
+        ErrorCode::NotFound,
+        ErrorClass::Tree,
+        format!("'{}' leads out of the tree", path.display()),
+    )
+}
+
 #[cfg(test)]
 mod tests {
 
     extern crate tempfile;
 
-    use super::{GitOps, LibGitOps};
+    use super::{
+        signatures, write_blob, AnnotatedTag, BlameHunk, GitOps, Keyring, LibGitOps, RefEntry,
+        RefKind, SignatureStatus, SigningKey, TreeFile,
+    };
 
-    use git2::{Repository, Signature, Time};
+    use git2::{ObjectType, Repository, Signature, Time};
     use std::fs;
-    use std::io::Write;
+    use std::io::{self, Write};
     use std::path::{Path, PathBuf};
     use std::str;
 
@@ -101,7 +882,7 @@ mod tests {
         path: &str,
     ) -> Result<Vec<u8>, git2::Error> {
         let gh = LibGitOps {};
-        gh.cat_file(repo_path, reference, &PathBuf::from(path))
+        gh.cat_file(repo_path, reference, &PathBuf::from(path), false)
     }
 
     fn git_cat_file_err(repo_path: &Repository, reference: &str, path: &str) -> git2::Error {
@@ -169,6 +950,58 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_find_blob_tells_size_and_write_blob_reads_part() {
+        with_repo("file content", "dir/existing.file", |repo, _| {
+            let blob = LibGitOps {}
+                .find_blob(repo, "master", Path::new("dir/existing.file"), false)
+                .expect("should be ok");
+            assert_eq!(blob.size, 12);
+
+            let mut part = Vec::new();
+            write_blob(repo, blob.id, 5, 7, &mut part).expect("should be ok");
+            assert_eq!(part, b"content");
+
+            let err = write_blob(repo, blob.id, 5, 8, &mut Vec::new()).expect_err("should fail");
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        })
+    }
+
+    // read tree tests
+
+    fn git_read_tree(
+        repo_path: &Repository,
+        reference: &str,
+        path: &str,
+    ) -> Result<Vec<TreeFile>, git2::Error> {
+        let gh = LibGitOps {};
+        gh.read_tree(repo_path, reference, &PathBuf::from(path))
+    }
+
+    #[test]
+    fn test_read_tree_with_nested_file() {
+        with_repo("file content", "dir/sub/existing.file", |repo, _| {
+            let res = git_read_tree(repo, "master", "dir").expect("should be ok");
+            assert_eq!(
+                res,
+                vec![TreeFile {
+                    path: PathBuf::from("sub/existing.file"),
+                    mode: 0o100644,
+                    size: 12,
+                }]
+            );
+        })
+    }
+
+    #[test]
+    fn test_read_tree_with_file() {
+        with_repo("file content", "dir/existing.file", |repo, _| {
+            let res =
+                git_read_tree(repo, "master", "dir/existing.file").expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::NotFound);
+        })
+    }
+
     // ls tests
 
     // Converts a vec of string like things into a vec of owned paths.
@@ -184,7 +1017,7 @@ mod tests {
         path: &str,
     ) -> Result<Vec<PathBuf>, git2::Error> {
         let gh = LibGitOps {};
-        gh.ls_dir(repo_path, reference, &PathBuf::from(path))
+        gh.ls_dir(repo_path, reference, &PathBuf::from(path), false)
     }
 
     fn git_ls_dir_err(repo_path: &Repository, reference: &str, directory: &str) -> git2::Error {
@@ -242,6 +1075,171 @@ mod tests {
         })
     }
 
+    // symlink tests
+
+    fn with_symlinks<F>(callback: F)
+    where
+        F: Fn(&Repository),
+    {
+        with_repo("name: prod-eu\n", "config/prod-eu.yaml", |repo, _| {
+            let workdir = repo.workdir().expect("should have a workdir");
+            let links = [
+                ("config/prod.yaml", "prod-eu.yaml"),
+                ("config/current.yaml", "prod.yaml"),
+                ("latest", "config"),
+                ("loop-a", "loop-b"),
+                ("loop-b", "loop-a"),
+                ("outside.yaml", "../outside.yaml"),
+                ("absolute.yaml", "/etc/passwd"),
+            ];
+
+            let mut index = repo.index().expect("can't open index");
+            for (link, target) in links.iter() {
+                std::os::unix::fs::symlink(target, workdir.join(link)).expect("can't symlink");
+                index.add_path(Path::new(link)).expect("can't add symlink");
+            }
+            let tree = index
+                .write_tree()
+                .and_then(|tid| repo.find_tree(tid))
+                .expect("can't write tree");
+            let parent = repo.head().and_then(|head| head.peel_to_commit()).unwrap();
+            let sig = parent.author();
+            repo.commit(Some("HEAD"), &sig, &sig, "Add symlinks", &tree, &[&parent])
+                .expect("can't commit symlinks");
+
+            callback(repo);
+        })
+    }
+
+    fn git_cat_symlink(repo: &Repository, path: &str) -> Result<Vec<u8>, git2::Error> {
+        LibGitOps {}.cat_file(repo, "master", Path::new(path), true)
+    }
+
+    #[test]
+    fn test_cat_file_follows_symlinks() {
+        with_symlinks(|repo| {
+            for path in &[
+                "config/prod.yaml",
+                "config/current.yaml",
+                "latest/prod.yaml",
+            ] {
+                let res = git_cat_symlink(repo, path).expect("should be ok");
+                assert_eq!(str::from_utf8(&res).unwrap(), "name: prod-eu\n", "{}", path);
+            }
+        })
+    }
+
+    #[test]
+    fn test_cat_file_without_following_symlinks() {
+        with_symlinks(|repo| {
+            let res = git_cat_file(repo, "master", "config/prod.yaml").expect("should be ok");
+            assert_eq!(str::from_utf8(&res).unwrap(), "prod-eu.yaml");
+        })
+    }
+
+    #[test]
+    fn test_cat_file_with_symlink_loop() {
+        with_symlinks(|repo| {
+            let res = git_cat_symlink(repo, "loop-a").expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Invalid);
+        })
+    }
+
+    #[test]
+    fn test_cat_file_with_symlink_out_of_the_tree() {
+        with_symlinks(|repo| {
+            for path in &["outside.yaml", "absolute.yaml", "../config/prod.yaml"] {
+                let res = git_cat_symlink(repo, path).expect_err("should be an error");
+                assert_eq!(res.code(), git2::ErrorCode::NotFound, "{}", path);
+            }
+        })
+    }
+
+    #[test]
+    fn test_ls_dir_follows_symlinks() {
+        with_symlinks(|repo| {
+            let res = LibGitOps {}
+                .ls_dir(repo, "master", Path::new("latest"), true)
+                .expect("should be ok");
+            assert_eq!(
+                res,
+                as_path_bufs!(["current.yaml", "prod-eu.yaml", "prod.yaml"])
+            );
+        })
+    }
+
+    // blame tests
+
+    #[test]
+    fn test_blame_file_with_lines_changed_by_two_commits() {
+        with_repo("a: 1\nb: 2\n", "dir/existing.file", |repo, first_sha| {
+            let path = repo.workdir().unwrap().join("dir/existing.file");
+            fs::write(&path, "a: 1\nb: 3\nc: 4\n").expect("can't change file");
+
+            let mut index = repo.index().expect("can't open index");
+            index
+                .add_path(Path::new("dir/existing.file"))
+                .expect("can't add file to index");
+            let tree = index
+                .write_tree()
+                .and_then(|tid| repo.find_tree(tid))
+                .expect("can't write tree");
+            let parent = repo.head().and_then(|head| head.peel_to_commit()).unwrap();
+            let sig = Signature::new(
+                "Bar McFooson",
+                "bar@example.com",
+                &Time::new(987_654_321, 0),
+            )
+            .unwrap();
+            let second_sha = repo
+                .commit(Some("HEAD"), &sig, &sig, "Change b", &tree, &[&parent])
+                .expect("can't commit change")
+                .to_string();
+
+            let res = LibGitOps {}
+                .blame_file(repo, "master", Path::new("dir/existing.file"))
+                .expect("should be ok");
+            assert_eq!(
+                res,
+                vec![
+                    BlameHunk {
+                        start_line: 1,
+                        lines: 1,
+                        commit: first_sha.to_string(),
+                        author_name: "Foo McBarson".to_string(),
+                        author_email: "foo.mcbarson@iamarealboy.net".to_string(),
+                        timestamp: 123_456_789,
+                    },
+                    BlameHunk {
+                        start_line: 2,
+                        lines: 2,
+                        commit: second_sha,
+                        author_name: "Bar McFooson".to_string(),
+                        author_email: "bar@example.com".to_string(),
+                        timestamp: 987_654_321,
+                    },
+                ]
+            );
+
+            // Earlier commits only know about their own history.
+            let res = LibGitOps {}
+                .blame_file(repo, "this-is-a-tag", Path::new("dir/existing.file"))
+                .expect("should be ok");
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].lines, 2);
+        })
+    }
+
+    #[test]
+    fn test_blame_file_with_dir() {
+        with_repo("content", "dir/existing.file", |repo, _| {
+            let res = LibGitOps {}
+                .blame_file(repo, "master", Path::new("dir"))
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::NotFound);
+        })
+    }
+
     // resolve tests
 
     fn git_resolve(repo_path: &Repository, reference: &str) -> Result<String, git2::Error> {
@@ -282,6 +1280,378 @@ mod tests {
         })
     }
 
+    // refs tests
+
+    #[test]
+    fn test_list_refs() {
+        with_repo("file content", "dir/existing.file", |repo, commit_sha| {
+            let commit = repo.find_commit(commit_sha.parse().unwrap()).unwrap();
+            repo.branch("feature", &commit, false).unwrap();
+            repo.tag_lightweight("v1.0", commit.as_object(), false)
+                .unwrap();
+            repo.reference("refs/remotes/origin/master", commit.id(), false, "Fetch")
+                .unwrap();
+            repo.reference_symbolic(
+                "refs/remotes/origin/HEAD",
+                "refs/remotes/origin/master",
+                false,
+                "Clone",
+            )
+            .unwrap();
+
+            let refs = LibGitOps {}.list_refs(repo).expect("should be ok");
+            let entry = |name: &str, kind, tag| RefEntry {
+                name: name.to_string(),
+                kind,
+                target: commit_sha.to_string(),
+                tag,
+            };
+            assert_eq!(
+                refs,
+                vec![
+                    entry("feature", RefKind::Branch, None),
+                    entry("master", RefKind::Branch, None),
+                    entry("origin/master", RefKind::Remote, None),
+                    entry(
+                        "this-is-a-tag",
+                        RefKind::Tag,
+                        Some(AnnotatedTag {
+                            id: repo
+                                .refname_to_id("refs/tags/this-is-a-tag")
+                                .unwrap()
+                                .to_string(),
+                            tagger_name: Some("Foo McBarson".to_string()),
+                            tagger_email: Some("foo.mcbarson@iamarealboy.net".to_string()),
+                            timestamp: Some(123_456_789),
+                            message: Some("This is a tag.".to_string()),
+                        })
+                    ),
+                    entry("v1.0", RefKind::Tag, None),
+                ]
+            );
+        })
+    }
+
+    // write tests
+
+    #[test]
+    fn test_create_tag() {
+        with_repo("file content", "dir/existing.file", |repo, commit_sha| {
+            let ops = LibGitOps {};
+            let sig = Signature::new("Foo McBarson", "foo@example.com", &Time::new(42, 0)).unwrap();
+
+            let tag = ops
+                .create_tag(repo, "v1.0", "master", Some((&sig, "Release\n")), None)
+                .expect("should be ok");
+            assert_eq!(tag.target, commit_sha);
+            assert_eq!(
+                tag.tag.and_then(|tag| tag.message),
+                Some("Release\n".to_string())
+            );
+
+            let tag = ops
+                .create_tag(repo, "v1.1", commit_sha, None, None)
+                .expect("should be ok");
+            assert_eq!((tag.target.as_str(), tag.tag), (commit_sha, None));
+
+            let res = ops
+                .create_tag(repo, "v1.0", "master", None, None)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Exists);
+
+            let res = ops
+                .create_tag(repo, "v1..2", "master", None, None)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::InvalidSpec);
+        })
+    }
+
+    #[test]
+    fn test_create_signed_tag() {
+        with_repo("file content", "dir/existing.file", |repo, commit_sha| {
+            let dir = tempfile::tempdir().unwrap();
+            let key = signatures::tests::ssh_key(dir.path(), "key");
+            let keyring = Keyring {
+                gpg: None,
+                ssh_allowed_signers: Some(signatures::tests::allowed_signers(
+                    dir.path(),
+                    "gitkv",
+                    &key,
+                )),
+            };
+            let signing_key = SigningKey::Ssh(key);
+            let ops = LibGitOps {};
+            let sig =
+                Signature::new("Foo McBarson", "foo@example.com", &Time::new(42, 60)).unwrap();
+
+            let tag = ops
+                .create_tag(
+                    repo,
+                    "v1.0",
+                    "master",
+                    Some((&sig, "Release")),
+                    Some(&signing_key),
+                )
+                .expect("should be ok");
+            assert_eq!(tag.target, commit_sha);
+            let annotation = tag.tag.expect("should be annotated");
+            assert_eq!(annotation.tagger_email.as_deref(), Some("foo@example.com"));
+            assert_eq!(annotation.timestamp, Some(42));
+            assert!(annotation
+                .message
+                .unwrap()
+                .starts_with("Release\n-----BEGIN SSH SIGNATURE-----"));
+
+            assert_eq!(
+                ops.verify_signature(repo, "v1.0", commit_sha, &keyring)
+                    .unwrap(),
+                SignatureStatus::Verified {
+                    signer: "gitkv".to_string()
+                }
+            );
+
+            let count_objects = || {
+                let mut count = 0;
+                repo.odb()
+                    .unwrap()
+                    .foreach(|_| {
+                        count += 1;
+                        true
+                    })
+                    .unwrap();
+                count
+            };
+            let objects = count_objects();
+            let res = ops
+                .create_tag(
+                    repo,
+                    "v1.0",
+                    "master",
+                    Some((&sig, "Release")),
+                    Some(&signing_key),
+                )
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Exists);
+            assert_eq!(count_objects(), objects);
+
+            let res = ops
+                .create_tag(repo, "v1.1", "master", None, Some(&signing_key))
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Invalid);
+
+            let res = ops
+                .create_tag(
+                    repo,
+                    "v1.2",
+                    "master",
+                    Some((&sig, "Release")),
+                    Some(&SigningKey::Ssh(dir.path().join("missing"))),
+                )
+                .expect_err("should be an error");
+            assert_eq!(res.class(), git2::ErrorClass::Os);
+        })
+    }
+
+    #[test]
+    fn test_create_branch() {
+        with_repo("file content", "dir/existing.file", |repo, commit_sha| {
+            let ops = LibGitOps {};
+            let branch = ops
+                .create_branch(repo, "release/1.x", "this-is-a-tag")
+                .expect("should be ok");
+            assert_eq!(branch.name, "release/1.x");
+            assert_eq!(branch.target, commit_sha);
+
+            let res = ops
+                .create_branch(repo, "master", commit_sha)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Exists);
+        })
+    }
+
+    #[test]
+    fn test_move_branch() {
+        with_repo("file content", "dir/existing.file", |repo, first_sha| {
+            let ops = LibGitOps {};
+            let first = repo.find_commit(first_sha.parse().unwrap()).unwrap();
+            let sig = first.author();
+            let second = repo
+                .commit(
+                    None,
+                    &sig,
+                    &sig,
+                    "Second",
+                    &first.tree().unwrap(),
+                    &[&first],
+                )
+                .unwrap()
+                .to_string();
+            ops.create_branch(repo, "release", first_sha).unwrap();
+
+            let res = ops
+                .move_branch(repo, "release", &second, &second, false)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Modified);
+
+            let branch = ops
+                .move_branch(repo, "release", &second, first_sha, false)
+                .expect("should be ok");
+            assert_eq!(branch.target, second);
+
+            let res = ops
+                .move_branch(repo, "release", first_sha, &second, false)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::NotFastForward);
+
+            let res = ops
+                .move_branch(repo, "release", &second, &second[..7], false)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::InvalidSpec);
+        })
+    }
+
+    #[test]
+    fn test_force_move_and_delete_with_protection_rules() {
+        with_repo("file content", "dir/existing.file", |repo, first_sha| {
+            let ops = LibGitOps {};
+            let first = repo.find_commit(first_sha.parse().unwrap()).unwrap();
+            let sig = first.author();
+            let second = repo
+                .commit(
+                    None,
+                    &sig,
+                    &sig,
+                    "Second",
+                    &first.tree().unwrap(),
+                    &[&first],
+                )
+                .unwrap()
+                .to_string();
+            ops.create_branch(repo, "feature", &second).unwrap();
+            ops.create_branch(repo, "release/1.x", &second).unwrap();
+            let mut config = repo.config().unwrap();
+            config
+                .set_multivar(crate::PROTECTED_REF_KEY, "^$", "release/*")
+                .unwrap();
+
+            let branch = ops
+                .move_branch(repo, "feature", first_sha, &second, true)
+                .expect("should be ok");
+            assert_eq!(branch.target, first_sha);
+
+            let res = ops
+                .move_branch(repo, "release/1.x", first_sha, &second, true)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Locked);
+
+            let res = ops
+                .delete_ref(repo, RefKind::Branch, "release/1.x", &second)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Locked);
+
+            let res = ops
+                .delete_ref(repo, RefKind::Branch, "feature", &second)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::Modified);
+
+            ops.delete_ref(repo, RefKind::Branch, "feature", first_sha)
+                .expect("should be ok");
+            assert!(repo.find_reference("refs/heads/feature").is_err());
+        })
+    }
+
+    // point in time tests
+
+    #[test]
+    fn test_resolve_ref_at_follows_first_parents() {
+        with_repo("file content", "dir/existing.file", |repo, first_sha| {
+            let first = repo.find_commit(first_sha.parse().unwrap()).unwrap();
+            let tree = first.tree().unwrap();
+            let commit = |seconds, parents: &[&git2::Commit]| {
+                let sig = Signature::new("Foo McBarson", "foo@example.com", &Time::new(seconds, 0))
+                    .unwrap();
+                let oid = repo
+                    .commit(None, &sig, &sig, "Commit", &tree, parents)
+                    .unwrap();
+                repo.find_commit(oid).unwrap()
+            };
+
+            // A branch committed to before the second commit of master, but merged after it.
+            let branch = commit(123_456_800, &[&first]);
+            let second = commit(123_456_900, &[&first]);
+            let merge = commit(123_457_000, &[&second, &branch]);
+            repo.reference("refs/heads/master", merge.id(), true, "Merge")
+                .unwrap();
+
+            let at = |at| LibGitOps {}.resolve_ref_at(repo, "master", at).unwrap();
+            assert_eq!(at(123_458_000), merge.id().to_string());
+            assert_eq!(at(123_457_000), merge.id().to_string());
+            assert_eq!(at(123_456_999), second.id().to_string());
+            assert_eq!(at(123_456_850), first_sha);
+            assert_eq!(at(123_456_789), first_sha);
+        })
+    }
+
+    #[test]
+    fn test_resolve_ref_at_before_the_first_commit() {
+        with_repo("file content", "dir/existing.file", |repo, _| {
+            let res = LibGitOps {}
+                .resolve_ref_at(repo, "master", 123_456_788)
+                .expect_err("should be an error");
+            assert_eq!(res.code(), git2::ErrorCode::NotFound);
+            assert_eq!(res.class(), git2::ErrorClass::Reference);
+        })
+    }
+
+    // signature tests
+
+    #[test]
+    fn test_verify_signature_of_a_signed_tag() {
+        with_repo("file content", "dir/existing.file", |repo, commit| {
+            let dir = tempfile::tempdir().unwrap();
+            let key = signatures::tests::ssh_key(dir.path(), "key");
+            let keyring = Keyring {
+                gpg: None,
+                ssh_allowed_signers: Some(signatures::tests::allowed_signers(
+                    dir.path(),
+                    "maintainer",
+                    &key,
+                )),
+            };
+
+            let content = format!(
+                "object {}\ntype commit\ntag signed-tag\ntagger Foo McBarson <foo@example.com> 0 +0000\n\nSigned\n",
+                commit
+            );
+            let signature = SigningKey::Ssh(key).sign(content.as_bytes()).unwrap();
+            let tag = repo
+                .odb()
+                .unwrap()
+                .write(ObjectType::Tag, (content + &signature).as_bytes())
+                .unwrap();
+            repo.reference("refs/tags/signed-tag", tag, false, "Signed tag")
+                .unwrap();
+
+            let gh = LibGitOps {};
+            assert_eq!(
+                gh.verify_signature(repo, "signed-tag", commit, &keyring)
+                    .unwrap(),
+                SignatureStatus::Verified {
+                    signer: "maintainer".to_string()
+                }
+            );
+            assert_eq!(
+                gh.verify_signature(repo, "this-is-a-tag", commit, &keyring)
+                    .unwrap(),
+                SignatureStatus::Unsigned
+            );
+            assert_eq!(
+                gh.verify_signature(repo, commit, commit, &keyring).unwrap(),
+                SignatureStatus::Unsigned
+            );
+        })
+    }
+
     pub fn with_repo<F>(file_contents: &str, file: &str, callback: F)
     where
         F: Fn(&Repository, &str),
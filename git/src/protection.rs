@@ -0,0 +1,142 @@
+use git2::{Error, ErrorClass, ErrorCode, Repository};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// The git config key, in the config of each repository, holding globs of the references that
+/// can't be deleted or moved to a commit that doesn't descend from theirs. It can be given many
+/// times, ie. `git config --add gitkv.protectedRef 'refs/tags/*'`.
+pub const PROTECTED_REF_KEY: &str = "gitkv.protectedRef";
+
+/// The git config key holding globs of the references that can only be fast-forwarded, but can
+/// still be deleted.
+pub const FAST_FORWARD_ONLY_KEY: &str = "gitkv.fastForwardOnly";
+
+/// The updates of a reference that protection rules may refuse. Creating references and
+/// fast-forwarding them are always allowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefUpdate {
+    /// Moving it to a commit that doesn't descend from its current one.
+    ForceMove,
+    Delete,
+}
+
+/// Which references of a repository are protected from which updates. Globs match full reference
+/// names, `*` matching across `/` too, while globs not starting with `refs/` match branch names,
+/// so `main` protects `refs/heads/main`.
+pub struct ProtectionRules {
+    protected: GlobSet,
+    fast_forward_only: GlobSet,
+}
+
+impl ProtectionRules {
+    /// Reads the rules from the config of the repository. Broken globs fail the writes rather than
+    /// leave the references unprotected.
+    pub fn load(repo: &Repository) -> Result<ProtectionRules, Error> {
+        let config = repo.config()?;
+        Ok(ProtectionRules {
+            protected: globs(&config, PROTECTED_REF_KEY)?,
+            fast_forward_only: globs(&config, FAST_FORWARD_ONLY_KEY)?,
+        })
+    }
+
+    /// Fails with `Locked` when the update of the reference, given by its full name, isn't allowed.
+    pub fn check(&self, refname: &str, update: RefUpdate) -> Result<(), Error> {
+        let refused = match update {
+            RefUpdate::ForceMove => {
+                self.protected.is_match(refname) || self.fast_forward_only.is_match(refname)
+            }
+            RefUpdate::Delete => self.protected.is_match(refname),
+        };
+
+        if refused {
+            let action = match update {
+                RefUpdate::ForceMove => "moved to a commit that doesn't descend from its own",
+                RefUpdate::Delete => "deleted",
+            };
+            return Err(Error::new(
+                ErrorCode::Locked,
+                ErrorClass::Reference,
+                format!("'{}' is protected and can't be {}", refname, action),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn globs(config: &git2::Config, key: &str) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+
+    let entries = match config.multivar(key, None) {
+        Ok(entries) => entries,
+        Err(err) if err.code() == ErrorCode::NotFound => return Ok(GlobSet::empty()),
+        Err(err) => return Err(err),
+    };
+    for entry in &entries {
+        let entry = entry?;
+        let pattern = String::from_utf8_lossy(entry.value_bytes());
+        let pattern = if pattern.starts_with("refs/") {
+            pattern.into_owned()
+        } else {
+            format!("refs/heads/{}", pattern)
+        };
+
+        builder.add(Glob::new(&pattern).map_err(|err| invalid(key, &err))?);
+    }
+
+    builder.build().map_err(|err| invalid(key, &err))
+}
+
+fn invalid(key: &str, err: &globset::Error) -> Error {
+    Error::new(
+        ErrorCode::Invalid,
+        ErrorClass::Config,
+        format!("{}: {}", key, err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(protected: &[&str], fast_forward_only: &[&str]) -> ProtectionRules {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        for glob in protected {
+            config.set_multivar(PROTECTED_REF_KEY, "^$", glob).unwrap();
+        }
+        for glob in fast_forward_only {
+            config
+                .set_multivar(FAST_FORWARD_ONLY_KEY, "^$", glob)
+                .unwrap();
+        }
+
+        ProtectionRules::load(&repo).unwrap()
+    }
+
+    #[test]
+    fn test_check_protected_refs() {
+        let rules = rules(&["refs/tags/*", "main"], &[]);
+
+        for refname in &["refs/tags/v1", "refs/tags/release/1.0", "refs/heads/main"] {
+            for update in &[RefUpdate::ForceMove, RefUpdate::Delete] {
+                let err = rules.check(refname, *update).expect_err(refname);
+                assert_eq!(err.code(), ErrorCode::Locked);
+            }
+        }
+        assert!(rules.check("refs/heads/main-2", RefUpdate::Delete).is_ok());
+    }
+
+    #[test]
+    fn test_check_fast_forward_only_refs() {
+        let rules = rules(&[], &["release/*"]);
+
+        assert!(rules
+            .check("refs/heads/release/1.x", RefUpdate::ForceMove)
+            .is_err());
+        assert!(rules
+            .check("refs/heads/release/1.x", RefUpdate::Delete)
+            .is_ok());
+        assert!(rules.check("refs/heads/main", RefUpdate::ForceMove).is_ok());
+    }
+}
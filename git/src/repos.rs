@@ -0,0 +1,536 @@
+use git2::{ErrorCode, Repository};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+const GIT_SUFFIX: &str = ".git";
+
+/// Separates the directories a repository is nested in from its name in its key.
+pub const NAMESPACE_SEPARATOR: &str = "/";
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The directory the repositories are looked for in could not be read.
+    Root { path: PathBuf, source: io::Error },
+    /// Two different repositories would be served under the same key.
+    Collision {
+        key: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    /// A repository explicitly mapped to a key could not be opened.
+    Mapping {
+        key: String,
+        path: PathBuf,
+        source: git2::Error,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Root { path, source } => write!(
+                f,
+                "Failed to read repos directory '{}': {}",
+                path.display(),
+                source
+            ),
+            LoadError::Collision { key, first, second } => write!(
+                f,
+                "Both '{}' and '{}' would be served as repo '{}'",
+                first.display(),
+                second.display(),
+                key
+            ),
+            LoadError::Mapping { key, path, source } => write!(
+                f,
+                "Failed to open '{}' for repo '{}': {}",
+                path.display(),
+                key,
+                source
+            ),
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LoadError::Root { source, .. } => Some(source),
+            LoadError::Collision { .. } => None,
+            LoadError::Mapping { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Derives the key a repository is served under from the name of its directory. Only the `.git`
+/// suffix conventionally given to bare repositories is dropped, so `configs.git` is served as
+/// `configs` but `my.configs` stays `my.configs`.
+pub fn repo_key(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let key = name.strip_suffix(GIT_SUFFIX).unwrap_or(name);
+
+    if key.is_empty() {
+        None
+    } else {
+        Some(key.to_string())
+    }
+}
+
+/// What became of a directory examined while loading the repositories.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RepoStatus {
+    /// A repository, served under `key`.
+    Opened { key: String, bare: bool },
+    /// Not a repository. It's looked into for namespaced repositories if the depth allows it.
+    NotARepository,
+    /// A repository that is not served for the given reason, ie. it's mapped to another key.
+    Skipped(String),
+    /// A directory that couldn't be opened or read, ie. a corrupt repository or one we lack
+    /// permissions for.
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepoDiagnostic {
+    pub path: PathBuf,
+    pub status: RepoStatus,
+}
+
+/// The repositories to serve by key, along with a diagnostic for every directory examined to find
+/// them.
+pub struct LoadedRepos {
+    pub repos: HashMap<String, Repository>,
+    pub diagnostics: Vec<RepoDiagnostic>,
+}
+
+/// Opens the repositories explicitly mapped to a key plus every repository, bare or not, found
+/// under `root_path` up to `max_depth` directories deep. Repositories found in nested directories
+/// are namespaced by them, so `team/service.git` is served as `team/service`.
+///
+/// Directories that can't be opened are not served but reported in the diagnostics. Two
+/// repositories ending up with the same key is an error rather than one silently replacing the
+/// other.
+pub fn load_repos(
+    root_path: &Path,
+    mappings: &[(String, PathBuf)],
+    max_depth: usize,
+) -> Result<LoadedRepos, LoadError> {
+    let mut repos = HashMap::new();
+    let mut diagnostics = Vec::new();
+
+    for (key, path) in mappings {
+        let repo = Repository::open(path).map_err(|source| LoadError::Mapping {
+            key: key.clone(),
+            path: path.clone(),
+            source,
+        })?;
+
+        diagnostics.push(opened(key.clone(), path.clone(), &repo));
+        insert(&mut repos, key.clone(), path.clone(), repo)?;
+    }
+
+    // A mapped repository living under the root is served only under the key it was mapped to.
+    let mapped: Vec<(PathBuf, &String)> = mappings
+        .iter()
+        .filter_map(|(key, path)| path.canonicalize().ok().map(|path| (path, key)))
+        .collect();
+
+    let entries = fs::read_dir(root_path).map_err(|source| LoadError::Root {
+        path: root_path.to_path_buf(),
+        source,
+    })?;
+
+    let mut discovered = Vec::new();
+    discover(
+        entries,
+        &[],
+        max_depth,
+        &mapped,
+        &mut discovered,
+        &mut diagnostics,
+    );
+
+    // Sorted so that collisions are always reported the same way.
+    discovered.sort_by(|a, b| a.1.cmp(&b.1));
+    diagnostics.sort_by(|a, b| a.path.cmp(&b.path));
+
+    for (key, path, repo) in discovered {
+        insert(&mut repos, key, path, repo)?;
+    }
+
+    Ok(LoadedRepos {
+        repos: repos
+            .into_iter()
+            .map(|(key, (_, repo))| (key, repo))
+            .collect(),
+        diagnostics,
+    })
+}
+
+// Looks for repositories among `entries`, descending into the directories that aren't one while
+// `depth` allows it. Repositories are never descended into.
+fn discover(
+    entries: fs::ReadDir,
+    namespace: &[String],
+    depth: usize,
+    mapped: &[(PathBuf, &String)],
+    found: &mut Vec<(String, PathBuf, Repository)>,
+    diagnostics: &mut Vec<RepoDiagnostic>,
+) {
+    if depth == 0 {
+        return;
+    }
+
+    for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        if !path.is_dir() {
+            continue;
+        }
+
+        let mapped_key = path.canonicalize().ok().and_then(|canonical| {
+            mapped
+                .iter()
+                .find(|(mapped_path, _)| *mapped_path == canonical)
+                .map(|(_, key)| key)
+        });
+
+        if let Some(key) = mapped_key {
+            diagnostics.push(RepoDiagnostic {
+                status: RepoStatus::Skipped(format!("served as repo '{}'", key)),
+                path,
+            });
+            continue;
+        }
+
+        match Repository::open(&path) {
+            Ok(repo) => match repo_key(&path) {
+                Some(name) => {
+                    let key = namespace
+                        .iter()
+                        .chain(Some(&name))
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(NAMESPACE_SEPARATOR);
+                    diagnostics.push(opened(key.clone(), path.clone(), &repo));
+                    found.push((key, path, repo));
+                }
+                None => diagnostics.push(RepoDiagnostic {
+                    status: RepoStatus::Skipped(
+                        "can't derive a repo name from the directory name".to_string(),
+                    ),
+                    path,
+                }),
+            },
+            Err(err) if err.code() != ErrorCode::NotFound => diagnostics.push(RepoDiagnostic {
+                status: RepoStatus::Failed(err.to_string()),
+                path,
+            }),
+            // Reading the directory tells apart directories that aren't repositories from the ones
+            // we can't look into.
+            Err(_) => match fs::read_dir(&path) {
+                Ok(entries) => {
+                    let segment = path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .map(String::from);
+
+                    diagnostics.push(RepoDiagnostic {
+                        status: RepoStatus::NotARepository,
+                        path,
+                    });
+
+                    if let Some(segment) = segment {
+                        let mut namespace = namespace.to_vec();
+                        namespace.push(segment);
+                        discover(entries, &namespace, depth - 1, mapped, found, diagnostics);
+                    }
+                }
+                Err(err) => diagnostics.push(RepoDiagnostic {
+                    status: RepoStatus::Failed(err.to_string()),
+                    path,
+                }),
+            },
+        }
+    }
+}
+
+fn opened(key: String, path: PathBuf, repo: &Repository) -> RepoDiagnostic {
+    RepoDiagnostic {
+        status: RepoStatus::Opened {
+            key,
+            bare: repo.is_bare(),
+        },
+        path,
+    }
+}
+
+fn insert(
+    repos: &mut HashMap<String, (PathBuf, Repository)>,
+    key: String,
+    path: PathBuf,
+    repo: Repository,
+) -> Result<(), LoadError> {
+    match repos.entry(key) {
+        Entry::Occupied(entry) => Err(LoadError::Collision {
+            key: entry.key().clone(),
+            first: entry.get().0.clone(),
+            second: path,
+        }),
+        Entry::Vacant(entry) => {
+            entry.insert((path, repo));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate tempfile;
+
+    use super::{load_repos, repo_key, LoadError, RepoDiagnostic, RepoStatus};
+
+    use git2::Repository;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn repo_root() -> tempfile::TempDir {
+        tempfile::Builder::new()
+            .prefix("testgitrepos")
+            .tempdir()
+            .expect("can't create tmp dir")
+    }
+
+    fn keys(root: &Path, mappings: &[(String, PathBuf)]) -> Vec<String> {
+        let mut keys: Vec<String> = load_repos(root, mappings, 1)
+            .expect("should load repos")
+            .repos
+            .into_keys()
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn load_repos_err(root: &Path, mappings: &[(String, PathBuf)]) -> LoadError {
+        load_repos(root, mappings, 1)
+            .err()
+            .expect("should be an error")
+    }
+
+    #[test]
+    fn test_repo_key_drops_git_suffix() {
+        assert_eq!(
+            repo_key(Path::new("/repos/configs.git")).unwrap(),
+            "configs"
+        );
+        assert_eq!(repo_key(Path::new("/repos/configs")).unwrap(), "configs");
+    }
+
+    #[test]
+    fn test_repo_key_keeps_other_dots() {
+        assert_eq!(
+            repo_key(Path::new("/repos/my.configs")).unwrap(),
+            "my.configs"
+        );
+        assert_eq!(
+            repo_key(Path::new("/repos/my.configs.git")).unwrap(),
+            "my.configs"
+        );
+    }
+
+    #[test]
+    fn test_repo_key_without_name() {
+        assert_eq!(repo_key(Path::new("/repos/.git")), None);
+    }
+
+    #[test]
+    fn test_load_repos_with_bare_and_non_bare_repos() {
+        let root = repo_root();
+        Repository::init_bare(root.path().join("bare.git")).unwrap();
+        Repository::init(root.path().join("my.configs")).unwrap();
+        fs::create_dir(root.path().join("not-a-repo")).unwrap();
+
+        assert_eq!(keys(root.path(), &[]), vec!["bare", "my.configs"]);
+    }
+
+    #[test]
+    fn test_load_repos_with_colliding_keys() {
+        let root = repo_root();
+        Repository::init_bare(root.path().join("configs.git")).unwrap();
+        Repository::init(root.path().join("configs")).unwrap();
+
+        match load_repos_err(root.path(), &[]) {
+            LoadError::Collision { key, first, second } => {
+                assert_eq!(key, "configs");
+                assert_eq!(first, root.path().join("configs"));
+                assert_eq!(second, root.path().join("configs.git"));
+            }
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_load_repos_with_mapping() {
+        let root = repo_root();
+        let elsewhere = repo_root();
+        Repository::init(root.path().join("my.configs")).unwrap();
+        Repository::init(elsewhere.path().join("other")).unwrap();
+
+        let mappings = vec![
+            ("configs".to_string(), root.path().join("my.configs")),
+            ("other-configs".to_string(), elsewhere.path().join("other")),
+        ];
+
+        assert_eq!(
+            keys(root.path(), &mappings),
+            vec!["configs", "other-configs"]
+        );
+    }
+
+    #[test]
+    fn test_load_repos_with_mapping_colliding_with_discovered_repo() {
+        let root = repo_root();
+        let elsewhere = repo_root();
+        Repository::init(root.path().join("configs")).unwrap();
+        Repository::init(elsewhere.path().join("other")).unwrap();
+
+        let mappings = vec![("configs".to_string(), elsewhere.path().join("other"))];
+
+        match load_repos_err(root.path(), &mappings) {
+            LoadError::Collision { key, .. } => assert_eq!(key, "configs"),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_load_repos_with_mapping_to_non_repo() {
+        let root = repo_root();
+        let mappings = vec![("configs".to_string(), root.path().join("nope"))];
+
+        match load_repos_err(root.path(), &mappings) {
+            LoadError::Mapping { key, .. } => assert_eq!(key, "configs"),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    fn nested_keys(root: &Path, max_depth: usize) -> Vec<String> {
+        let loaded = load_repos(root, &[], max_depth).expect("should load repos");
+        let mut keys: Vec<String> = loaded.repos.into_keys().collect();
+        keys.sort();
+        keys
+    }
+
+    fn nested_repo_root() -> tempfile::TempDir {
+        let root = repo_root();
+        Repository::init(root.path().join("top")).unwrap();
+        Repository::init_bare(root.path().join("team/service.git")).unwrap();
+        Repository::init(root.path().join("team/other")).unwrap();
+        Repository::init(root.path().join("org/team/deep")).unwrap();
+        // Repositories nested inside other repositories are never looked for.
+        Repository::init(root.path().join("top/vendored")).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_load_repos_only_looks_at_the_root_by_default() {
+        let root = nested_repo_root();
+
+        assert_eq!(nested_keys(root.path(), 1), vec!["top"]);
+    }
+
+    #[test]
+    fn test_load_repos_with_namespaces() {
+        let root = nested_repo_root();
+
+        assert_eq!(
+            nested_keys(root.path(), 2),
+            vec!["team/other", "team/service", "top"]
+        );
+        assert_eq!(
+            nested_keys(root.path(), 3),
+            vec!["org/team/deep", "team/other", "team/service", "top"]
+        );
+    }
+
+    #[test]
+    fn test_load_repos_with_missing_root() {
+        let root = repo_root();
+
+        match load_repos_err(&root.path().join("nope"), &[]) {
+            LoadError::Root { path, .. } => assert_eq!(path, root.path().join("nope")),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_load_repos_diagnostics() {
+        let root = repo_root();
+        Repository::init_bare(root.path().join("bare.git")).unwrap();
+        Repository::init(root.path().join("team/service")).unwrap();
+        Repository::init(root.path().join("mapped")).unwrap();
+        fs::write(root.path().join("not-a-dir"), "").unwrap();
+        // A repository with an unparseable config is broken rather than no repository at all.
+        Repository::init(root.path().join("broken")).unwrap();
+        fs::write(root.path().join("broken/.git/config"), "[core\n").unwrap();
+
+        let mappings = vec![("other".to_string(), root.path().join("mapped"))];
+        let loaded = load_repos(root.path(), &mappings, 2).expect("should load repos");
+
+        let diagnostic = |path: &str, status: RepoStatus| RepoDiagnostic {
+            path: root.path().join(path),
+            status,
+        };
+
+        assert_eq!(loaded.diagnostics.len(), 6);
+        assert_eq!(
+            loaded.diagnostics[0],
+            diagnostic(
+                "bare.git",
+                RepoStatus::Opened {
+                    key: "bare".to_string(),
+                    bare: true
+                }
+            )
+        );
+        match &loaded.diagnostics[1] {
+            RepoDiagnostic {
+                path,
+                status: RepoStatus::Failed(_),
+            } => assert_eq!(path, &root.path().join("broken")),
+            diagnostic => panic!("unexpected diagnostic {:?}", diagnostic),
+        }
+        assert_eq!(
+            loaded.diagnostics[2],
+            diagnostic(
+                "mapped",
+                RepoStatus::Opened {
+                    key: "other".to_string(),
+                    bare: false
+                }
+            )
+        );
+        assert_eq!(
+            loaded.diagnostics[3],
+            diagnostic(
+                "mapped",
+                RepoStatus::Skipped("served as repo 'other'".to_string())
+            )
+        );
+        assert_eq!(
+            loaded.diagnostics[4],
+            diagnostic("team", RepoStatus::NotARepository)
+        );
+        assert_eq!(
+            loaded.diagnostics[5],
+            diagnostic(
+                "team/service",
+                RepoStatus::Opened {
+                    key: "team/service".to_string(),
+                    bare: false
+                }
+            )
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use git2::{Error, ErrorClass, ErrorCode, Oid, Repository};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const PGP_SIGNATURE: &[u8] = b"-----BEGIN PGP SIGNATURE-----";
+const SSH_SIGNATURE: &[u8] = b"-----BEGIN SSH SIGNATURE-----";
+// The namespace git signs commits and tags in with SSH keys, so that signatures made for anything
+// else aren't taken for theirs.
+const SSH_NAMESPACE: &str = "git";
+
+/// The keys whose signatures of commits and tags are trusted. Signatures are checked with the same
+/// programs git uses, which have to be installed: `gpgv` for GPG and `ssh-keygen` for SSH.
+#[derive(Clone, Debug, Default)]
+pub struct Keyring {
+    /// A keyring of OpenPGP public keys, as exported by `gpg --export`. All of its keys are
+    /// trusted.
+    pub gpg: Option<PathBuf>,
+    /// An allowed signers file, like git's `gpg.ssh.allowedSignersFile`.
+    pub ssh_allowed_signers: Option<PathBuf>,
+}
+
+impl Keyring {
+    /// Whether no key is trusted, in which case no signature can be verified.
+    pub fn is_empty(&self) -> bool {
+        self.gpg.is_none() && self.ssh_allowed_signers.is_none()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignatureStatus {
+    Unsigned,
+    /// Signed, but either not by a key of the keyring or not matching the signed content.
+    Unverified,
+    /// Signed by a key of the keyring, which is told by its fingerprint for GPG and by the
+    /// principal it's allowed for for SSH.
+    Verified {
+        signer: String,
+    },
+}
+
+/// The key the tags gitkv creates are signed with, using the same programs as to check signatures.
+/// Only annotated tags are signed, signing commits waits for gitkv to write some.
+#[derive(Clone, Debug)]
+pub enum SigningKey {
+    /// The ID of a GPG key of the keyring of the user gitkv runs as, ie. its fingerprint.
+    Gpg(String),
+    /// The private key file of an SSH key.
+    Ssh(PathBuf),
+}
+
+impl SigningKey {
+    /// Makes an armored detached signature of the data. Keys with a passphrase can only be used
+    /// through an agent holding them unlocked, as there's nobody to ask for it.
+    pub fn sign(&self, data: &[u8]) -> Result<String, Error> {
+        let output = match self {
+            SigningKey::Gpg(key) => run(
+                Command::new("gpg")
+                    .args(["--batch", "--armor", "--detach-sign", "--local-user"])
+                    .arg(key),
+                data,
+            )?,
+            SigningKey::Ssh(key) => run(
+                Command::new("ssh-keygen")
+                    .args(["-Y", "sign", "-n", SSH_NAMESPACE, "-f"])
+                    .arg(key),
+                data,
+            )?,
+        };
+
+        if !output.status.success() {
+            return Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                format!(
+                    "Can't sign: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        String::from_utf8(output.stdout).map_err(|_| {
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Os,
+                "Can't sign: the signature isn't valid UTF-8",
+            )
+        })
+    }
+}
+
+impl Keyring {
+    /// Checks a detached signature of the given data. Signatures of a kind the keyring has no keys
+    /// for are unverified, while failing to run the program checking them is an error.
+    pub fn verify(&self, signature: &[u8], data: &[u8]) -> Result<SignatureStatus, Error> {
+        if signature.starts_with(PGP_SIGNATURE) {
+            match &self.gpg {
+                Some(keyring) => verify_gpg(keyring, signature, data),
+                None => Ok(SignatureStatus::Unverified),
+            }
+        } else if signature.starts_with(SSH_SIGNATURE) {
+            match &self.ssh_allowed_signers {
+                Some(allowed_signers) => verify_ssh(allowed_signers, signature, data),
+                None => Ok(SignatureStatus::Unverified),
+            }
+        } else {
+            Ok(SignatureStatus::Unverified)
+        }
+    }
+
+    /// The status of the signature of a commit.
+    pub fn verify_commit(&self, repo: &Repository, id: Oid) -> Result<SignatureStatus, Error> {
+        match repo.extract_signature(&id, None) {
+            Ok((signature, data)) => self.verify(&signature, &data),
+            Err(err) if err.code() == ErrorCode::NotFound => Ok(SignatureStatus::Unsigned),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The status of the signature of an annotated tag, which git appends to its message.
+    pub fn verify_tag(&self, repo: &Repository, id: Oid) -> Result<SignatureStatus, Error> {
+        let odb = repo.odb()?;
+        let object = odb.read(id)?;
+        let content = object.data();
+
+        match signature_start(content) {
+            Some(start) => self.verify(&content[start..], &content[..start]),
+            None => Ok(SignatureStatus::Unsigned),
+        }
+    }
+}
+
+// Where the signature appended to a tag starts, on a line of its own.
+fn signature_start(content: &[u8]) -> Option<usize> {
+    [PGP_SIGNATURE, SSH_SIGNATURE]
+        .iter()
+        .filter_map(|marker| {
+            content
+                .windows(marker.len())
+                .enumerate()
+                .rev()
+                .find(|(start, window)| {
+                    window == marker && (*start == 0 || content[start - 1] == b'\n')
+                })
+                .map(|(start, _)| start)
+        })
+        .max()
+}
+
+fn verify_gpg(keyring: &Path, signature: &[u8], data: &[u8]) -> Result<SignatureStatus, Error> {
+    let signature_file = signature_file(signature)?;
+    let output = run(
+        Command::new("gpgv")
+            .arg("--status-fd=1")
+            .arg("--keyring")
+            .arg(keyring)
+            .arg(signature_file.path())
+            .arg("-"),
+        data,
+    )?;
+
+    // Only a good signature made by a key of the keyring is followed by its fingerprint.
+    let signer = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(String::from);
+
+    Ok(match signer {
+        Some(signer) if output.status.success() => SignatureStatus::Verified { signer },
+        _ => SignatureStatus::Unverified,
+    })
+}
+
+// Like git, finds the principals allowed to have made the signature and then checks it was made
+// by one of them.
+fn verify_ssh(
+    allowed_signers: &Path,
+    signature: &[u8],
+    data: &[u8],
+) -> Result<SignatureStatus, Error> {
+    let signature_file = signature_file(signature)?;
+    let principals = run(
+        Command::new("ssh-keygen")
+            .args(["-Y", "find-principals", "-f"])
+            .arg(allowed_signers)
+            .arg("-s")
+            .arg(signature_file.path()),
+        &[],
+    )?;
+    if !principals.status.success() {
+        return Ok(SignatureStatus::Unverified);
+    }
+
+    for principal in String::from_utf8_lossy(&principals.stdout).lines() {
+        let output = run(
+            Command::new("ssh-keygen")
+                .args(["-Y", "verify", "-n", SSH_NAMESPACE, "-f"])
+                .arg(allowed_signers)
+                .arg("-I")
+                .arg(principal)
+                .arg("-s")
+                .arg(signature_file.path()),
+            data,
+        )?;
+        if output.status.success() {
+            return Ok(SignatureStatus::Verified {
+                signer: principal.to_string(),
+            });
+        }
+    }
+
+    Ok(SignatureStatus::Unverified)
+}
+
+// Both programs read detached signatures from a file.
+fn signature_file(signature: &[u8]) -> Result<tempfile::NamedTempFile, Error> {
+    let mut file = tempfile::NamedTempFile::new()
+        .map_err(|err| os_error("Can't write the signature", &err))?;
+    file.write_all(signature)
+        .map_err(|err| os_error("Can't write the signature", &err))?;
+    Ok(file)
+}
+
+// Runs the command with the given input, failing only when it can't be run at all.
+fn run(command: &mut Command, input: &[u8]) -> Result<Output, Error> {
+    let context = format!("Can't run {}", command.get_program().to_string_lossy());
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| os_error(&context, &err))?;
+
+    // A program failing before reading all of its input is told apart by its exit status.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input);
+    }
+
+    child
+        .wait_with_output()
+        .map_err(|err| os_error(&context, &err))
+}
+
+fn os_error(context: &str, err: &std::io::Error) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Os,
+        format!("{}: {}", context, err),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::fs;
+
+    /// Generates an SSH key pair without a passphrase, answering the path of its private key.
+    pub(crate) fn ssh_key(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        let output = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        path
+    }
+
+    /// An allowed signers file trusting the key for the given principal.
+    pub(crate) fn allowed_signers(dir: &Path, principal: &str, key: &Path) -> PathBuf {
+        let public_key = fs::read_to_string(key.with_extension("pub")).unwrap();
+        let path = dir.join("allowed_signers");
+        fs::write(&path, format!("{} {}", principal, public_key)).unwrap();
+        path
+    }
+
+    fn commit(repo: &Repository, key: Option<&Path>) -> Oid {
+        let signature = Signature::now("Maintainer", "maintainer@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let content = repo
+            .commit_create_buffer(&signature, &signature, "Signed", &tree, &[])
+            .unwrap();
+        let content = content.as_str().unwrap();
+
+        match key {
+            Some(key) => repo
+                .commit_signed(
+                    content,
+                    &SigningKey::Ssh(key.to_path_buf())
+                        .sign(content.as_bytes())
+                        .unwrap(),
+                    None,
+                )
+                .unwrap(),
+            None => repo
+                .commit(None, &signature, &signature, "Unsigned", &tree, &[])
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_verify_commit_signed_with_ssh() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init_bare(dir.path().join("repo")).unwrap();
+        let trusted = ssh_key(dir.path(), "trusted");
+        let untrusted = ssh_key(dir.path(), "untrusted");
+        let keyring = Keyring {
+            gpg: None,
+            ssh_allowed_signers: Some(allowed_signers(
+                dir.path(),
+                "maintainer@example.com",
+                &trusted,
+            )),
+        };
+
+        assert_eq!(
+            keyring
+                .verify_commit(&repo, commit(&repo, Some(&trusted)))
+                .unwrap(),
+            SignatureStatus::Verified {
+                signer: "maintainer@example.com".to_string()
+            }
+        );
+        assert_eq!(
+            keyring
+                .verify_commit(&repo, commit(&repo, Some(&untrusted)))
+                .unwrap(),
+            SignatureStatus::Unverified
+        );
+        assert_eq!(
+            keyring.verify_commit(&repo, commit(&repo, None)).unwrap(),
+            SignatureStatus::Unsigned
+        );
+    }
+
+    #[test]
+    fn test_verify_without_keys_for_the_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = ssh_key(dir.path(), "key");
+        let signature = SigningKey::Ssh(key).sign(b"data").unwrap();
+
+        assert_eq!(
+            Keyring::default()
+                .verify(signature.as_bytes(), b"data")
+                .unwrap(),
+            SignatureStatus::Unverified
+        );
+    }
+
+    #[test]
+    fn test_verify_tampered_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = ssh_key(dir.path(), "key");
+        let keyring = Keyring {
+            gpg: None,
+            ssh_allowed_signers: Some(allowed_signers(dir.path(), "maintainer", &key)),
+        };
+        let signature = SigningKey::Ssh(key).sign(b"data").unwrap();
+
+        assert_eq!(
+            keyring.verify(signature.as_bytes(), b"other data").unwrap(),
+            SignatureStatus::Unverified
+        );
+    }
+
+    #[test]
+    fn test_signature_start() {
+        let tag = b"object 1234\ntag v1\n\nRelease\n-----BEGIN SSH SIGNATURE-----\nabc\n";
+        assert_eq!(signature_start(tag), Some(28));
+        assert_eq!(signature_start(b"object 1234\ntag v1\n\nRelease\n"), None);
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+/// A submodule found along a path, which continues in the submodule's repository.
+#[derive(Debug, PartialEq)]
+pub struct SubmoduleEntry {
+    /// Where the submodule is in the tree of the repository holding it.
+    pub path: PathBuf,
+    /// The URL of the submodule as given in `.gitmodules`, if there's one for its path.
+    pub url: Option<String>,
+    /// The SHA of the commit of the submodule pinned by the tree.
+    pub commit: String,
+    /// The rest of the path, within the submodule.
+    pub rest: PathBuf,
+}
+
+/// Finds the URL of the submodule at `path` in the content of a `.gitmodules` file, which is in
+/// the git config format, ie:
+///
+/// ```text
+/// [submodule "lib"]
+///     path = vendor/lib
+///     url = https://github.com/org/lib.git
+/// ```
+pub fn submodule_url(gitmodules: &str, path: &Path) -> Option<String> {
+    let mut section_path = None;
+    let mut section_url = None;
+
+    for line in gitmodules.lines().map(str::trim) {
+        if line.starts_with('[') {
+            if section_path.as_deref() == Some(path) {
+                return section_url;
+            }
+            section_path = None;
+            section_url = None;
+            continue;
+        }
+
+        match line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), unquote(value.trim())))
+        {
+            Some(("path", value)) => section_path = Some(PathBuf::from(value)),
+            Some(("url", value)) => section_url = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    section_url.filter(|_| section_path.as_deref() == Some(path))
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Normalises a repository URL so that the different ways of writing the same one match, ie. with
+/// or without a trailing `.git`.
+pub fn normalise_url(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GITMODULES: &str = r#"
+[submodule "lib"]
+	path = vendor/lib
+	url = https://github.com/org/lib.git
+[submodule "schemas"]
+	url = "git@github.com:org/schemas"
+	path = schemas
+"#;
+
+    #[test]
+    fn test_submodule_url() {
+        assert_eq!(
+            submodule_url(GITMODULES, Path::new("vendor/lib")),
+            Some("https://github.com/org/lib.git".to_string())
+        );
+        assert_eq!(
+            submodule_url(GITMODULES, Path::new("schemas")),
+            Some("git@github.com:org/schemas".to_string())
+        );
+        assert_eq!(submodule_url(GITMODULES, Path::new("vendor")), None);
+    }
+
+    #[test]
+    fn test_normalise_url() {
+        assert_eq!(
+            normalise_url("https://github.com/org/lib.git"),
+            "https://github.com/org/lib"
+        );
+        assert_eq!(
+            normalise_url("https://github.com/org/lib/"),
+            "https://github.com/org/lib"
+        );
+    }
+}
@@ -1,19 +1,232 @@
 use actix::dev::MessageResponse;
 use actix::{Actor, Context, Handler, Message};
-use git::{git2::Repository, GitOps, LibGitOps};
+use git::{
+    git2::{self, ErrorCode, Oid, Repository, Signature},
+    normalise_url, BlameHunk, BlobHeader, GitOps, Keyring, LfsPointer, LibGitOps, RefEntry,
+    RefKind, SignatureStatus, SigningKey, TreeFile,
+};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info_span, Span};
 
+// Every message carries the span it was sent from, as the actor handles it on a different thread.
+// Spans created while handling the message are its children, so that the time a message waits in
+// the mailbox shows up as the gap between the two.
+
+/// Tells the commit that last changed each line of a file.
+#[derive(Message)]
+#[rtype(result = "BlameFileResponse")]
+pub struct BlameFile {
+    pub repo_key: String,
+    pub reference: String,
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+#[derive(MessageResponse)]
+pub struct BlameFileResponse(pub Result<Resolved<Vec<BlameHunk>>, String>);
+
+/// Reads a file, or only the given range of its bytes. Files larger than `max_size` are refused
+/// unless the range asked for fits in it, so that large files can still be read in parts. Git LFS
+/// pointers are resolved to the file they point to, read from the local LFS object store.
 #[derive(Message)]
 #[rtype(result = "CatFileResponse")]
 pub struct CatFile {
     pub repo_key: String,
     pub reference: String,
     pub path: PathBuf,
+    pub range: Option<ByteRange>,
+    pub max_size: Option<u64>,
+    /// Serve Git LFS pointers as they are stored instead of the file they point to.
+    pub raw: bool,
+    /// Descend into the submodules along the path.
+    pub submodules: bool,
+    /// Read the files symlinks lead to in the tree instead of the paths they link to.
+    pub follow_symlinks: bool,
+    pub span: Span,
+}
+
+#[derive(MessageResponse)]
+pub struct CatFileResponse(pub Result<Resolved<BlobPart>, CatFileError>);
+
+/// A range of bytes as given in an HTTP `Range` header, its ends being inclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteRange {
+    FromTo(u64, u64),
+    From(u64),
+    Last(u64),
+}
+
+impl ByteRange {
+    /// The first and last byte of the range within a file of the given size, if any.
+    pub fn resolve(self, size: u64) -> Option<(u64, u64)> {
+        match self {
+            _ if size == 0 => None,
+            ByteRange::FromTo(from, to) if from <= to && from < size => {
+                Some((from, to.min(size - 1)))
+            }
+            ByteRange::From(from) if from < size => Some((from, size - 1)),
+            ByteRange::Last(last) if last > 0 => Some((size - last.min(size), size - 1)),
+            _ => None,
+        }
+    }
+}
+
+/// The bytes read from a file along with its whole size. `range` holds the first and last byte
+/// read when only part of the file was asked for.
+pub struct BlobPart {
+    pub size: u64,
+    pub range: Option<(u64, u64)>,
+    pub content: BlobContent,
+}
+
+/// The content of a file read, left where it's stored to be read as it's sent unless it had to be
+/// read already.
+#[derive(Clone)]
+pub enum BlobContent {
+    Bytes(Vec<u8>),
+    /// `length` bytes from `first` of a blob of the repository at `repo`.
+    Blob {
+        repo: PathBuf,
+        id: Oid,
+        first: u64,
+        length: u64,
+    },
+    /// `length` bytes of a file on disk from `first`, ie. a Git LFS object.
+    File {
+        path: PathBuf,
+        first: u64,
+        length: u64,
+    },
+}
+
+impl BlobContent {
+    /// How many bytes there are.
+    pub fn length(&self) -> u64 {
+        match self {
+            BlobContent::Bytes(bytes) => bytes.len() as u64,
+            BlobContent::Blob { length, .. } | BlobContent::File { length, .. } => *length,
+        }
+    }
+
+    /// Writes every byte, reading files and loose objects a bit at a time.
+    pub fn write_to<W: Write + ?Sized>(self, writer: &mut W) -> io::Result<()> {
+        match self {
+            BlobContent::Bytes(bytes) => writer.write_all(&bytes),
+            BlobContent::Blob {
+                repo,
+                id,
+                first,
+                length,
+            } => {
+                // Repositories can't be shared with the actor, so the one to read from is opened
+                // again on the thread writing.
+                let repo = Repository::open(repo).map_err(io::Error::other)?;
+                git::write_blob(&repo, id, first, length, writer)
+            }
+            BlobContent::File {
+                path,
+                first,
+                length,
+            } => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(first))?;
+                let copied = io::copy(&mut file.take(length), writer)?;
+                if copied < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads every byte in memory, ie. to parse them.
+    pub fn read(self) -> io::Result<Vec<u8>> {
+        match self {
+            BlobContent::Bytes(bytes) => Ok(bytes),
+            file => {
+                let mut bytes = Vec::with_capacity(file.length() as usize);
+                file.write_to(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CatFileError {
+    /// The repo, reference or file couldn't be found.
+    NotFound(String),
+    TooLarge {
+        path: PathBuf,
+        size: u64,
+        max_size: u64,
+    },
+    /// None of the bytes asked for are in the file.
+    Unsatisfiable { size: u64 },
+    /// The file is a Git LFS pointer to an object that isn't in the local object store.
+    LfsObjectMissing(String),
+}
+
+impl fmt::Display for CatFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatFileError::NotFound(message) => f.write_str(message),
+            CatFileError::TooLarge {
+                path,
+                size,
+                max_size,
+            } => write!(
+                f,
+                "'{}' is {} bytes, larger than the maximum of {}, read it in ranges instead",
+                path.display(),
+                size,
+                max_size
+            ),
+            CatFileError::Unsatisfiable { size } => {
+                write!(f, "The range is outside of the {} bytes of the file", size)
+            }
+            CatFileError::LfsObjectMissing(oid) => write!(
+                f,
+                "The LFS object {} is not present in the repository, read with raw=true to get its pointer",
+                oid
+            ),
+        }
+    }
+}
+
+/// Reads several files at the same commit, resolving the reference only once. None of them is
+/// read if any is larger than `max_size`.
+#[derive(Message)]
+#[rtype(result = "CatFilesResponse")]
+pub struct CatFiles {
+    pub repo_key: String,
+    pub reference: String,
+    pub paths: Vec<PathBuf>,
+    pub max_size: Option<u64>,
+    pub span: Span,
+}
+
+#[derive(MessageResponse)]
+pub struct CatFilesResponse(pub Result<Resolved<Vec<Vec<u8>>>, CatFileError>);
+
+/// Reads a file at a commit that was already resolved, which may not exist. Files larger than
+/// `max_size` are refused.
+#[derive(Message)]
+#[rtype(result = "FindFileResponse")]
+pub struct FindFile {
+    pub repo_key: String,
+    pub commit: String,
+    pub path: PathBuf,
+    pub max_size: Option<u64>,
+    pub span: Span,
 }
 
 #[derive(MessageResponse)]
-pub struct CatFileResponse(pub Result<Vec<u8>, String>);
+pub struct FindFileResponse(pub Result<Option<Vec<u8>>, CatFileError>);
 
 #[derive(Message)]
 #[rtype(result = "LsDirResponse")]
@@ -21,23 +234,177 @@ pub struct LsDir {
     pub repo_key: String,
     pub reference: String,
     pub path: PathBuf,
+    /// Descend into the submodules along the path.
+    pub submodules: bool,
+    /// List the directories symlinks lead to in the tree.
+    pub follow_symlinks: bool,
+    pub span: Span,
 }
 
 #[derive(MessageResponse)]
-pub struct LsDirResponse(pub Result<Vec<PathBuf>, String>);
+pub struct LsDirResponse(pub Result<Resolved<Vec<PathBuf>>, String>);
 
+/// Lists the branches, tags and remote-tracking branches of a repo.
+#[derive(Message)]
+#[rtype(result = "ListRefsResponse")]
+pub struct ListRefs {
+    pub repo_key: String,
+    pub span: Span,
+}
+
+#[derive(MessageResponse)]
+pub struct ListRefsResponse(pub Result<Vec<RefEntry>, String>);
+
+/// Tags a commit, with an annotated tag when given a message. Annotated tags are created by the
+/// given tagger, a name and an email, or by the identity in the config of the repo, and signed with
+/// the key the repos were given, if any.
+#[derive(Message)]
+#[rtype(result = "WriteRefResponse")]
+pub struct CreateTag {
+    pub repo_key: String,
+    pub name: String,
+    pub target: String,
+    pub message: Option<String>,
+    pub tagger: Option<(String, String)>,
+    pub span: Span,
+}
+
+#[derive(Message)]
+#[rtype(result = "WriteRefResponse")]
+pub struct CreateBranch {
+    pub repo_key: String,
+    pub name: String,
+    pub target: String,
+    pub span: Span,
+}
+
+/// Moves a branch, as long as it's still at `old_target`. Only fast-forwards it unless forced to,
+/// and the protection rules of the repo may refuse to force it.
+#[derive(Message)]
+#[rtype(result = "WriteRefResponse")]
+pub struct MoveBranch {
+    pub repo_key: String,
+    pub name: String,
+    pub target: String,
+    pub old_target: String,
+    pub force: bool,
+    pub span: Span,
+}
+
+/// Deletes a branch or tag, as long as it's still at `old_target` and its protection rules allow.
+#[derive(Message)]
+#[rtype(result = "DeleteRefResponse")]
+pub struct DeleteRef {
+    pub repo_key: String,
+    pub kind: RefKind,
+    pub name: String,
+    pub old_target: String,
+    pub span: Span,
+}
+
+#[derive(MessageResponse)]
+pub struct DeleteRefResponse(pub Result<(), WriteRefError>);
+
+/// The reference as written.
+#[derive(MessageResponse)]
+pub struct WriteRefResponse(pub Result<RefEntry, WriteRefError>);
+
+#[derive(Debug)]
+pub enum WriteRefError {
+    /// The repo, the reference or its target couldn't be found.
+    NotFound(String),
+    /// The name, target or tagger given aren't valid.
+    Invalid(String),
+    /// The reference already exists, or isn't at the commit it was expected to be at, or can't be
+    /// fast-forwarded to its new target.
+    Conflict(String),
+    /// The protection rules of the repo don't allow the update.
+    Protected(String),
+    Failed(String),
+}
+
+impl From<git2::Error> for WriteRefError {
+    fn from(err: git2::Error) -> Self {
+        match err.code() {
+            ErrorCode::NotFound => WriteRefError::NotFound(err.to_string()),
+            ErrorCode::InvalidSpec | ErrorCode::Invalid => WriteRefError::Invalid(err.to_string()),
+            ErrorCode::Exists | ErrorCode::Modified | ErrorCode::NotFastForward => {
+                WriteRefError::Conflict(err.to_string())
+            }
+            ErrorCode::Locked => WriteRefError::Protected(err.to_string()),
+            _ => WriteRefError::Failed(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for WriteRefError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteRefError::NotFound(message)
+            | WriteRefError::Invalid(message)
+            | WriteRefError::Conflict(message)
+            | WriteRefError::Protected(message)
+            | WriteRefError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Lists every file under a directory, ie. to archive it, without reading their content.
+#[derive(Message)]
+#[rtype(result = "ReadTreeResponse")]
+pub struct ReadTree {
+    pub repo_key: String,
+    pub reference: String,
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+#[derive(MessageResponse)]
+pub struct ReadTreeResponse(pub Result<Resolved<Vec<TreeFile>>, String>);
+
+/// Resolves a reference to the commit it points to, or pointed to at the given time in seconds
+/// since the Unix epoch.
 #[derive(Message)]
 #[rtype(result = "ResolveRefResponse")]
 pub struct ResolveRef {
     pub repo_key: String,
     pub reference: String,
+    pub at: Option<i64>,
+    pub span: Span,
 }
 
 #[derive(MessageResponse)]
 pub struct ResolveRefResponse(pub Result<String, String>);
 
+/// Tells whether a commit the reference resolved to is signed by a key of the keyring the repos
+/// were given.
+#[derive(Message)]
+#[rtype(result = "VerifyCommitResponse")]
+pub struct VerifyCommit {
+    pub repo_key: String,
+    pub reference: String,
+    pub commit: String,
+    pub span: Span,
+}
+
+#[derive(MessageResponse)]
+pub struct VerifyCommitResponse(pub Result<SignatureStatus, String>);
+
+/// A value read from a repository along with the SHA of the commit the reference resolved to, so
+/// that callers can tell which commit the value came from.
+pub struct Resolved<T> {
+    pub commit: String,
+    pub value: T,
+}
+
 pub struct GitRepos {
     repos: HashMap<String, Repository>,
+    /// The key of the repo serving each submodule, by its normalised URL.
+    submodule_repos: HashMap<String, String>,
+    /// The keys trusted to sign commits and tags.
+    keyring: Keyring,
+    /// The key to sign the annotated tags created with, if any.
+    signing_key: Option<SigningKey>,
     ops: Box<dyn GitOps>,
 }
 
@@ -46,24 +413,302 @@ impl Actor for GitRepos {
 }
 
 impl GitRepos {
+    /// Submodules are served by the repo with a remote at the URL of the submodule, if any.
     pub fn new(repos: HashMap<String, Repository>) -> GitRepos {
+        let submodule_repos = repos
+            .iter()
+            .flat_map(|(key, repo)| {
+                remote_urls(repo)
+                    .into_iter()
+                    .map(move |url| (normalise_url(&url).to_string(), key.clone()))
+            })
+            .collect();
+
         GitRepos {
             repos,
+            submodule_repos,
+            keyring: Keyring::default(),
+            signing_key: None,
             ops: Box::new(LibGitOps {}),
         }
     }
+
+    /// Serves the submodules at the given URLs with the repos with the given keys, whatever their
+    /// remotes are.
+    pub fn with_submodules(mut self, mappings: &[(String, String)]) -> GitRepos {
+        for (url, key) in mappings {
+            self.submodule_repos
+                .insert(normalise_url(url).to_string(), key.clone());
+        }
+        self
+    }
+
+    /// Verifies the signatures of commits and tags with the given keys, none being trusted
+    /// otherwise.
+    pub fn with_keyring(mut self, keyring: Keyring) -> GitRepos {
+        self.keyring = keyring;
+        self
+    }
+
+    /// Signs the annotated tags created with the given key, which are left unsigned otherwise.
+    pub fn with_signing_key(mut self, signing_key: SigningKey) -> GitRepos {
+        self.signing_key = Some(signing_key);
+        self
+    }
+
+    // Reads a whole blob, refusing it before reading it when it's larger than `max_size`.
+    fn read_whole(
+        &self,
+        repo: &Repository,
+        path: &Path,
+        blob: BlobHeader,
+        max_size: Option<u64>,
+    ) -> Result<Vec<u8>, CatFileError> {
+        if let Some(max_size) = max_size.filter(|max_size| blob.size > *max_size) {
+            return Err(CatFileError::TooLarge {
+                path: path.to_path_buf(),
+                size: blob.size,
+                max_size,
+            });
+        }
+
+        self.ops
+            .read_blob(repo, blob.id)
+            .map_err(|x| CatFileError::NotFound(x.to_string()))
+    }
+
+    // Follows the submodules along `path` into the repos serving them, when asked to, answering the
+    // repo, the commit and the path within it that the path leads to.
+    fn locate<'a>(
+        &'a self,
+        mut repo: &'a Repository,
+        commit: String,
+        path: &Path,
+        submodules: bool,
+    ) -> Result<(&'a Repository, String, PathBuf), String> {
+        let mut commit = commit;
+        let mut path = path.to_path_buf();
+
+        if !submodules {
+            return Ok((repo, commit, path));
+        }
+
+        while let Some(submodule) = self
+            .ops
+            .find_submodule(repo, &commit, &path)
+            .map_err(|x| x.to_string())?
+        {
+            repo = submodule
+                .url
+                .as_deref()
+                .and_then(|url| self.submodule_repos.get(normalise_url(url)))
+                .and_then(|key| self.repos.get(key))
+                .ok_or_else(|| {
+                    format!(
+                        "No repo found for the submodule at '{}' ({})",
+                        submodule.path.display(),
+                        submodule.url.as_deref().unwrap_or("without URL")
+                    )
+                })?;
+            commit = submodule.commit;
+            path = submodule.rest;
+        }
+
+        Ok((repo, commit, path))
+    }
+
+    // Fails like the writes themselves do when there's no repo with that key.
+    fn repo_to_write(&self, repo_key: &str) -> Result<&Repository, WriteRefError> {
+        self.repos.get(repo_key).ok_or_else(|| {
+            WriteRefError::NotFound(format!("No repo found with name '{}'", repo_key))
+        })
+    }
+}
+
+fn remote_urls(repo: &Repository) -> Vec<String> {
+    repo.remotes()
+        .map(|names| {
+            names
+                .iter()
+                .flatten()
+                .filter_map(|name| repo.find_remote(name).ok()?.url().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl Handler<BlameFile> for GitRepos {
+    type Result = BlameFileResponse;
+
+    fn handle(&mut self, req: BlameFile, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "BlameFile", repo = %req.repo_key).entered();
+
+        BlameFileResponse(match self.repos.get(&req.repo_key) {
+            Some(repo) => self
+                .ops
+                .resolve_ref(repo, &req.reference)
+                .and_then(|commit| {
+                    self.ops
+                        .blame_file(repo, &commit, &req.path)
+                        .map(|value| Resolved { commit, value })
+                })
+                .map_err(|x| x.to_string()),
+            None => Err(format!("No repo found with name '{}'", &req.repo_key)),
+        })
+    }
 }
 
 impl Handler<CatFile> for GitRepos {
     type Result = CatFileResponse;
 
     fn handle(&mut self, req: CatFile, _: &mut Self::Context) -> Self::Result {
-        CatFileResponse(match self.repos.get(&req.repo_key) {
+        let _span = info_span!(parent: &req.span, "CatFile", repo = %req.repo_key).entered();
+
+        let repo = match self.repos.get(&req.repo_key) {
+            Some(repo) => repo,
+            None => {
+                return CatFileResponse(Err(CatFileError::NotFound(format!(
+                    "No repo found with name '{}'",
+                    &req.repo_key
+                ))))
+            }
+        };
+
+        CatFileResponse(
+            self.ops
+                .resolve_ref(repo, &req.reference)
+                .map_err(|x| x.to_string())
+                .and_then(|commit| {
+                    let (repo, reference, path) =
+                        self.locate(repo, commit.clone(), &req.path, req.submodules)?;
+                    let blob = self
+                        .ops
+                        .find_blob(repo, &reference, &path, req.follow_symlinks)
+                        .map_err(|x| x.to_string())?;
+
+                    // Only blobs small enough to be pointers are read before answering.
+                    let pointer = if req.raw || blob.size > LfsPointer::MAX_SIZE {
+                        None
+                    } else {
+                        self.ops
+                            .read_blob(repo, blob.id)
+                            .map(|content| LfsPointer::parse(&content))
+                            .map_err(|x| x.to_string())?
+                    };
+
+                    Ok((commit, repo, blob, pointer))
+                })
+                .map_err(CatFileError::NotFound)
+                .and_then(|(commit, repo, blob, pointer)| {
+                    let value = match pointer {
+                        Some(pointer) => {
+                            let _span = info_span!("read_lfs_object", oid = %pointer.oid).entered();
+                            let object = pointer.object_path(repo);
+                            let size = fs::metadata(&object)
+                                .map_err(|_| CatFileError::LfsObjectMissing(pointer.oid.clone()))?
+                                .len();
+
+                            read_part(size, &req, |first, length| BlobContent::File {
+                                path: object,
+                                first,
+                                length,
+                            })?
+                        }
+                        None => read_part(blob.size, &req, |first, length| BlobContent::Blob {
+                            repo: repo.path().to_path_buf(),
+                            id: blob.id,
+                            first,
+                            length,
+                        })?,
+                    };
+
+                    Ok(Resolved { commit, value })
+                }),
+        )
+    }
+}
+
+// Reads the range of a file of the given size asked for, or the whole of it, with `read` given the
+// first byte and how many to read.
+fn read_part<F>(size: u64, req: &CatFile, read: F) -> Result<BlobPart, CatFileError>
+where
+    F: FnOnce(u64, u64) -> BlobContent,
+{
+    let range = match req.range {
+        Some(range) => Some(
+            range
+                .resolve(size)
+                .ok_or(CatFileError::Unsatisfiable { size })?,
+        ),
+        None => None,
+    };
+    let (first, last) = range.unwrap_or((0, size.saturating_sub(1)));
+    let length = if size == 0 { 0 } else { last - first + 1 };
+
+    if let Some(max_size) = req.max_size.filter(|max_size| length > *max_size) {
+        return Err(CatFileError::TooLarge {
+            path: req.path.clone(),
+            size,
+            max_size,
+        });
+    }
+
+    Ok(BlobPart {
+        size,
+        range,
+        content: read(first, length),
+    })
+}
+
+impl Handler<CatFiles> for GitRepos {
+    type Result = CatFilesResponse;
+
+    fn handle(&mut self, req: CatFiles, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "CatFiles", repo = %req.repo_key).entered();
+
+        CatFilesResponse(match self.repos.get(&req.repo_key) {
             Some(repo) => self
                 .ops
-                .cat_file(repo, &req.reference, &req.path)
-                .map_err(|x| x.to_string()),
-            None => Err(format!("No repo found with name '{}'", &req.repo_key)),
+                .resolve_ref(repo, &req.reference)
+                .map_err(|x| CatFileError::NotFound(x.to_string()))
+                .and_then(|commit| {
+                    req.paths
+                        .iter()
+                        .map(|path| {
+                            self.ops
+                                .find_blob(repo, &commit, path, false)
+                                .map_err(|x| CatFileError::NotFound(x.to_string()))
+                                .and_then(|blob| self.read_whole(repo, path, blob, req.max_size))
+                        })
+                        .collect::<Result<_, _>>()
+                        .map(|value| Resolved { commit, value })
+                }),
+            None => Err(CatFileError::NotFound(format!(
+                "No repo found with name '{}'",
+                &req.repo_key
+            ))),
+        })
+    }
+}
+
+impl Handler<FindFile> for GitRepos {
+    type Result = FindFileResponse;
+
+    fn handle(&mut self, req: FindFile, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "FindFile", repo = %req.repo_key).entered();
+
+        FindFileResponse(match self.repos.get(&req.repo_key) {
+            Some(repo) => match self.ops.find_blob(repo, &req.commit, &req.path, false) {
+                Ok(blob) => self
+                    .read_whole(repo, &req.path, blob, req.max_size)
+                    .map(Some),
+                Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+                Err(err) => Err(CatFileError::NotFound(err.to_string())),
+            },
+            None => Err(CatFileError::NotFound(format!(
+                "No repo found with name '{}'",
+                &req.repo_key
+            ))),
         })
     }
 }
@@ -72,10 +717,134 @@ impl Handler<LsDir> for GitRepos {
     type Result = LsDirResponse;
 
     fn handle(&mut self, req: LsDir, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "LsDir", repo = %req.repo_key).entered();
+
         LsDirResponse(match self.repos.get(&req.repo_key) {
             Some(repo) => self
                 .ops
-                .ls_dir(repo, &req.reference, &req.path)
+                .resolve_ref(repo, &req.reference)
+                .map_err(|x| x.to_string())
+                .and_then(|commit| {
+                    let (repo, reference, path) =
+                        self.locate(repo, commit.clone(), &req.path, req.submodules)?;
+                    self.ops
+                        .ls_dir(repo, &reference, &path, req.follow_symlinks)
+                        .map(|value| Resolved { commit, value })
+                        .map_err(|x| x.to_string())
+                }),
+            None => Err(format!("No repo found with name '{}'", &req.repo_key)),
+        })
+    }
+}
+
+impl Handler<ListRefs> for GitRepos {
+    type Result = ListRefsResponse;
+
+    fn handle(&mut self, req: ListRefs, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "ListRefs", repo = %req.repo_key).entered();
+
+        ListRefsResponse(match self.repos.get(&req.repo_key) {
+            Some(repo) => self.ops.list_refs(repo).map_err(|x| x.to_string()),
+            None => Err(format!("No repo found with name '{}'", &req.repo_key)),
+        })
+    }
+}
+
+impl Handler<CreateTag> for GitRepos {
+    type Result = WriteRefResponse;
+
+    fn handle(&mut self, req: CreateTag, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "CreateTag", repo = %req.repo_key).entered();
+
+        WriteRefResponse(self.repo_to_write(&req.repo_key).and_then(|repo| {
+            let message = match &req.message {
+                Some(message) => message,
+                None => {
+                    return Ok(self.ops.create_tag(
+                        repo,
+                        &req.name,
+                        &req.target,
+                        None,
+                        self.signing_key.as_ref(),
+                    )?)
+                }
+            };
+
+            let tagger = match &req.tagger {
+                Some((name, email)) => Signature::now(name, email),
+                None => repo.signature(),
+            }
+            .map_err(|err| {
+                WriteRefError::Invalid(format!("Can't tell who tags '{}': {}", req.name, err))
+            })?;
+
+            Ok(self.ops.create_tag(
+                repo,
+                &req.name,
+                &req.target,
+                Some((&tagger, message)),
+                self.signing_key.as_ref(),
+            )?)
+        }))
+    }
+}
+
+impl Handler<CreateBranch> for GitRepos {
+    type Result = WriteRefResponse;
+
+    fn handle(&mut self, req: CreateBranch, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "CreateBranch", repo = %req.repo_key).entered();
+
+        WriteRefResponse(
+            self.repo_to_write(&req.repo_key)
+                .and_then(|repo| Ok(self.ops.create_branch(repo, &req.name, &req.target)?)),
+        )
+    }
+}
+
+impl Handler<MoveBranch> for GitRepos {
+    type Result = WriteRefResponse;
+
+    fn handle(&mut self, req: MoveBranch, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "MoveBranch", repo = %req.repo_key).entered();
+
+        WriteRefResponse(self.repo_to_write(&req.repo_key).and_then(|repo| {
+            Ok(self
+                .ops
+                .move_branch(repo, &req.name, &req.target, &req.old_target, req.force)?)
+        }))
+    }
+}
+
+impl Handler<DeleteRef> for GitRepos {
+    type Result = DeleteRefResponse;
+
+    fn handle(&mut self, req: DeleteRef, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "DeleteRef", repo = %req.repo_key).entered();
+
+        DeleteRefResponse(self.repo_to_write(&req.repo_key).and_then(|repo| {
+            Ok(self
+                .ops
+                .delete_ref(repo, req.kind, &req.name, &req.old_target)?)
+        }))
+    }
+}
+
+impl Handler<ReadTree> for GitRepos {
+    type Result = ReadTreeResponse;
+
+    fn handle(&mut self, req: ReadTree, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "ReadTree", repo = %req.repo_key).entered();
+
+        ReadTreeResponse(match self.repos.get(&req.repo_key) {
+            Some(repo) => self
+                .ops
+                .resolve_ref(repo, &req.reference)
+                .and_then(|commit| {
+                    self.ops
+                        .read_tree(repo, &commit, &req.path)
+                        .map(|value| Resolved { commit, value })
+                })
                 .map_err(|x| x.to_string()),
             None => Err(format!("No repo found with name '{}'", &req.repo_key)),
         })
@@ -86,12 +855,54 @@ impl Handler<ResolveRef> for GitRepos {
     type Result = ResolveRefResponse;
 
     fn handle(&mut self, req: ResolveRef, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "ResolveRef", repo = %req.repo_key).entered();
+
         ResolveRefResponse(match self.repos.get(&req.repo_key) {
+            Some(repo) => match req.at {
+                Some(at) => self.ops.resolve_ref_at(repo, &req.reference, at),
+                None => self.ops.resolve_ref(repo, &req.reference),
+            }
+            .map_err(|x| x.to_string()),
+            None => Err(format!("No repo found with name '{}'", &req.repo_key)),
+        })
+    }
+}
+
+impl Handler<VerifyCommit> for GitRepos {
+    type Result = VerifyCommitResponse;
+
+    fn handle(&mut self, req: VerifyCommit, _: &mut Self::Context) -> Self::Result {
+        let _span = info_span!(parent: &req.span, "VerifyCommit", repo = %req.repo_key).entered();
+
+        VerifyCommitResponse(match self.repos.get(&req.repo_key) {
             Some(repo) => self
                 .ops
-                .resolve_ref(repo, &req.reference)
+                .verify_signature(repo, &req.reference, &req.commit, &self.keyring)
                 .map_err(|x| x.to_string()),
             None => Err(format!("No repo found with name '{}'", &req.repo_key)),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_byte_range() {
+        assert_eq!(ByteRange::FromTo(2, 4).resolve(10), Some((2, 4)));
+        assert_eq!(ByteRange::FromTo(2, 40).resolve(10), Some((2, 9)));
+        assert_eq!(ByteRange::From(7).resolve(10), Some((7, 9)));
+        assert_eq!(ByteRange::Last(3).resolve(10), Some((7, 9)));
+        assert_eq!(ByteRange::Last(30).resolve(10), Some((0, 9)));
+    }
+
+    #[test]
+    fn test_resolve_unsatisfiable_byte_range() {
+        assert_eq!(ByteRange::FromTo(4, 2).resolve(10), None);
+        assert_eq!(ByteRange::FromTo(10, 20).resolve(10), None);
+        assert_eq!(ByteRange::From(10).resolve(10), None);
+        assert_eq!(ByteRange::Last(0).resolve(10), None);
+        assert_eq!(ByteRange::From(0).resolve(0), None);
+    }
+}
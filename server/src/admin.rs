@@ -0,0 +1,113 @@
+use crate::AppState;
+use actix_web::{get, web, HttpResponse};
+use git::{RepoDiagnostic, RepoStatus};
+
+/// What became of a directory examined at startup, as listed by `GET /admin/repos`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RepoReport {
+    pub path: String,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bare: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl From<&RepoDiagnostic> for RepoReport {
+    fn from(diagnostic: &RepoDiagnostic) -> Self {
+        let (status, key, bare, reason) = match &diagnostic.status {
+            RepoStatus::Opened { key, bare } => ("opened", Some(key.clone()), Some(*bare), None),
+            RepoStatus::NotARepository => ("not_a_repository", None, None, None),
+            RepoStatus::Skipped(reason) => ("skipped", None, None, Some(reason.clone())),
+            RepoStatus::Failed(reason) => ("failed", None, None, Some(reason.clone())),
+        };
+
+        RepoReport {
+            path: diagnostic.path.display().to_string(),
+            status,
+            key,
+            bare,
+            reason,
+        }
+    }
+}
+
+/// Logs what became of every directory examined at startup, warning about the ones that couldn't
+/// be opened.
+pub fn log_diagnostics(diagnostics: &[RepoDiagnostic]) {
+    for diagnostic in diagnostics {
+        let path = diagnostic.path.display();
+        match &diagnostic.status {
+            RepoStatus::Opened { key, bare: true } => {
+                info!("Opened bare repo '{}' at {}", key, path)
+            }
+            RepoStatus::Opened { key, bare: false } => info!("Opened repo '{}' at {}", key, path),
+            RepoStatus::NotARepository => debug!("Not a repo: {}", path),
+            RepoStatus::Skipped(reason) => info!("Skipped repo at {}: {}", path, reason),
+            RepoStatus::Failed(reason) => warn!("Failed to open repo at {}: {}", path, reason),
+        }
+    }
+}
+
+/// The directories that couldn't be opened, which `--strict` refuses to start with.
+pub fn failures(diagnostics: &[RepoDiagnostic]) -> Vec<String> {
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| match &diagnostic.status {
+            RepoStatus::Failed(reason) => {
+                Some(format!("{}: {}", diagnostic.path.display(), reason))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[get("/admin/repos")]
+pub async fn repos(app_state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(app_state.repo_reports.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn reports_opened_repos_with_their_key() {
+        let report = RepoReport::from(&RepoDiagnostic {
+            path: PathBuf::from("/srv/team/service.git"),
+            status: RepoStatus::Opened {
+                key: "team/service".to_string(),
+                bare: true,
+            },
+        });
+
+        assert_eq!(
+            serde_json::to_value(report).unwrap(),
+            serde_json::json!({
+                "path": "/srv/team/service.git",
+                "status": "opened",
+                "key": "team/service",
+                "bare": true
+            })
+        );
+    }
+
+    #[test]
+    fn failures_lists_only_failed_directories() {
+        let diagnostics = vec![
+            RepoDiagnostic {
+                path: PathBuf::from("/srv/broken"),
+                status: RepoStatus::Failed("corrupt".to_string()),
+            },
+            RepoDiagnostic {
+                path: PathBuf::from("/srv/docs"),
+                status: RepoStatus::NotARepository,
+            },
+        ];
+
+        assert_eq!(failures(&diagnostics), vec!["/srv/broken: corrupt"]);
+    }
+}
@@ -0,0 +1,279 @@
+use flate2::{write::GzEncoder, Compression};
+use git::TreeFile;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError};
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::ZipWriter;
+
+// The git file mode of symlinks, whose content is the path they link to.
+const SYMLINK_MODE: i32 = 0o120_000;
+
+/// The formats directories can be archived as.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ArchiveFormat {
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+/// Archives files one at a time, keeping their paths and whether they are executable or symlinks.
+/// Entries carry no timestamp so that archiving the same tree always gives the same archive. What
+/// has been archived so far is taken as it's written, so that it's sent before the next file is
+/// read.
+pub struct Archiver {
+    buffer: Buffer,
+    writer: Writer,
+}
+
+enum Writer {
+    Tar(tar::Builder<Buffer>),
+    // Compressing writers are boxed, being much larger than the others.
+    TarGz(Box<tar::Builder<GzEncoder<Buffer>>>),
+    Zip(Box<ZipWriter<StreamWriter<Buffer>>>),
+}
+
+impl Archiver {
+    pub fn new(format: ArchiveFormat) -> Archiver {
+        let buffer = Buffer::default();
+        let writer = match format {
+            ArchiveFormat::Tar => Writer::Tar(tar::Builder::new(buffer.clone())),
+            ArchiveFormat::TarGz => Writer::TarGz(Box::new(tar::Builder::new(GzEncoder::new(
+                buffer.clone(),
+                Compression::default(),
+            )))),
+            // Zip archives are written as a stream, telling the size of each entry after its
+            // content.
+            ArchiveFormat::Zip => Writer::Zip(Box::new(ZipWriter::new_stream(buffer.clone()))),
+        };
+
+        Archiver { buffer, writer }
+    }
+
+    pub fn append(&mut self, file: &TreeFile, content: &[u8]) -> io::Result<()> {
+        match &mut self.writer {
+            Writer::Tar(builder) => append_tar(builder, file, content),
+            Writer::TarGz(builder) => append_tar(builder.as_mut(), file, content),
+            Writer::Zip(writer) => {
+                let name = file.path.to_string_lossy();
+                let options = SimpleFileOptions::default().unix_permissions(permissions(file.mode));
+
+                if file.mode == SYMLINK_MODE {
+                    writer.add_symlink(name, String::from_utf8_lossy(content), options)?;
+                } else {
+                    writer.start_file(name, options)?;
+                    writer.write_all(content)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes what has been archived since it was last taken.
+    pub fn take(&self) -> Vec<u8> {
+        self.buffer.take()
+    }
+
+    /// Ends the archive, giving what's left of it.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self.writer {
+            Writer::Tar(builder) => builder.into_inner().map(drop)?,
+            Writer::TarGz(builder) => builder.into_inner()?.finish().map(drop)?,
+            Writer::Zip(writer) => writer.finish().map(drop)?,
+        }
+        Ok(self.buffer.take())
+    }
+}
+
+fn append_tar<W: Write>(
+    builder: &mut tar::Builder<W>,
+    file: &TreeFile,
+    content: &[u8],
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(permissions(file.mode));
+
+    if file.mode == SYMLINK_MODE {
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        let target = String::from_utf8_lossy(content);
+        builder.append_link(&mut header, &file.path, target.as_ref())
+    } else {
+        header.set_size(content.len() as u64);
+        builder.append_data(&mut header, &file.path, content)
+    }
+}
+
+// What's written to the archive, until it's taken. It's shared by the writer and the archiver, as
+// the writers of each format own what they write to.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    fn take(&self) -> Vec<u8> {
+        mem::take(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Git only tells executable files apart from the rest, so these are the usual permissions of each.
+fn permissions(mode: i32) -> u32 {
+    if mode & 0o111 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+
+    fn files() -> Vec<(TreeFile, &'static [u8])> {
+        vec![
+            (
+                TreeFile {
+                    path: PathBuf::from("app.yaml"),
+                    mode: 0o100_644,
+                    size: 10,
+                },
+                b"name: app\n",
+            ),
+            (
+                TreeFile {
+                    path: PathBuf::from("bin/run.sh"),
+                    mode: 0o100_755,
+                    size: 10,
+                },
+                b"#!/bin/sh\n",
+            ),
+            (
+                TreeFile {
+                    path: PathBuf::from("current.yaml"),
+                    mode: SYMLINK_MODE,
+                    size: 8,
+                },
+                b"app.yaml",
+            ),
+        ]
+    }
+
+    // Archives the files, taking what's archived after each of them as it would be sent.
+    fn archive(format: ArchiveFormat) -> Vec<u8> {
+        let mut archiver = Archiver::new(format);
+        let mut archive = Vec::new();
+        for (file, content) in files() {
+            archiver.append(&file, content).unwrap();
+            archive.extend(archiver.take());
+        }
+        archive.extend(archiver.finish().unwrap());
+        archive
+    }
+
+    fn tar_entries<R: Read>(reader: R) -> Vec<(String, u32, String)> {
+        tar::Archive::new(reader)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().display().to_string();
+                let mode = entry.header().mode().unwrap();
+                let mut content = match entry.link_name().unwrap() {
+                    Some(target) => target.display().to_string(),
+                    None => String::new(),
+                };
+                entry.read_to_string(&mut content).unwrap();
+                (path, mode, content)
+            })
+            .collect()
+    }
+
+    fn expected_entries() -> Vec<(String, u32, String)> {
+        vec![
+            ("app.yaml".to_string(), 0o644, "name: app\n".to_string()),
+            ("bin/run.sh".to_string(), 0o755, "#!/bin/sh\n".to_string()),
+            ("current.yaml".to_string(), 0o644, "app.yaml".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_write_tar() {
+        let archive = archive(ArchiveFormat::Tar);
+        assert_eq!(tar_entries(archive.as_slice()), expected_entries());
+    }
+
+    #[test]
+    fn test_write_tar_gz() {
+        let archive = archive(ArchiveFormat::TarGz);
+        assert_eq!(
+            tar_entries(GzDecoder::new(archive.as_slice())),
+            expected_entries()
+        );
+    }
+
+    #[test]
+    fn test_write_zip() {
+        let archive = archive(ArchiveFormat::Zip);
+        let mut zip = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+
+        let mut script = String::new();
+        let mut file = zip.by_name("bin/run.sh").unwrap();
+        assert_eq!(file.unix_mode().map(|mode| mode & 0o777), Some(0o755));
+        file.read_to_string(&mut script).unwrap();
+        drop(file);
+        assert_eq!(script, "#!/bin/sh\n");
+
+        assert!(zip.by_name("current.yaml").unwrap().is_symlink());
+        assert_eq!(zip.len(), 3);
+    }
+
+    #[test]
+    fn test_take_what_was_archived() {
+        let (file, content) = files().remove(0);
+        let mut archiver = Archiver::new(ArchiveFormat::Tar);
+        archiver.append(&file, content).unwrap();
+
+        // A header block and a block of content.
+        assert_eq!(archiver.take().len(), 1024);
+        assert!(archiver.take().is_empty());
+        // Two empty blocks end the archive.
+        assert_eq!(archiver.finish().unwrap().len(), 1024);
+    }
+}
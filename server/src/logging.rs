@@ -0,0 +1,336 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpRequest};
+use env_logger::{fmt::Formatter, Env};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::{kv, Record};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{info_span, Instrument, Subscriber};
+use tracing_subscriber::layer::{self, Layer};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const ACCESS_LOG_TARGET: &str = "gitkv::access";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unknown log format '{}'", other)),
+        }
+    }
+}
+
+/// Initialises the global logger. Both formats honour `RUST_LOG`, but only the JSON one includes
+/// the structured fields of each record, which is what the per-request access log relies on.
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::from_env(Env::default().default_filter_or("gitkv=info"));
+
+    if format == LogFormat::Json {
+        builder.format(format_json);
+    }
+
+    builder.init();
+}
+
+fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let line = json_line(buf.timestamp().to_string(), record)?;
+    writeln!(buf, "{}", line)
+}
+
+// Lines logged while handling a request tell its ID, like its access record does.
+fn json_line(timestamp: String, record: &Record) -> io::Result<Value> {
+    let mut line = Map::new();
+    line.insert("timestamp".into(), timestamp.into());
+    line.insert("level".into(), record.level().as_str().into());
+    line.insert("target".into(), record.target().into());
+    line.insert("message".into(), record.args().to_string().into());
+    if let Some(request_id) = current_request_id() {
+        line.insert("request_id".into(), request_id.into());
+    }
+
+    record
+        .key_values()
+        .visit(&mut JsonFields(&mut line))
+        .map_err(|err| io::Error::other(err.to_string()))?;
+
+    Ok(Value::Object(line))
+}
+
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> kv::VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = serde_json::to_value(&value).map_err(kv::Error::boxed)?;
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Keeps the ID of each request in the span it's handled in, so that the lines logged within it,
+/// or within the spans under it, can tell which request they were logged for.
+pub struct RequestIds;
+
+struct RequestId(String);
+
+impl<S> Layer<S> for RequestIds
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let mut request_id = RequestIdField(None);
+        attrs.record(&mut request_id);
+
+        if let (Some(request_id), Some(span)) = (request_id.0, ctx.span(id)) {
+            span.extensions_mut().insert(RequestId(request_id));
+        }
+    }
+}
+
+struct RequestIdField(Option<String>);
+
+impl Visit for RequestIdField {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "request_id" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+// The ID of the request whose span, or one of the spans under it, is current.
+fn current_request_id() -> Option<String> {
+    tracing::dispatcher::get_default(|dispatch| {
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        let current = dispatch.current_span();
+        let span = registry.span(current.id()?)?;
+        span.scope()
+            .find_map(|span| span.extensions().get::<RequestId>().map(|id| id.0.clone()))
+    })
+}
+
+/// The reference a handler read from, as given by the client or defaulted.
+#[derive(Clone, Debug)]
+pub struct Reference(pub String);
+
+/// The SHA of the commit a handler resolved the reference to.
+#[derive(Clone, Debug)]
+pub struct ResolvedCommit(pub String);
+
+/// Records the reference a handler is about to read so it shows up in the access log.
+pub fn record_reference(req: &HttpRequest, reference: &str) {
+    req.extensions_mut()
+        .insert(Reference(reference.to_string()));
+}
+
+/// Records the commit a handler resolved the reference to so it shows up in the access log.
+pub fn record_commit(req: &HttpRequest, commit: &str) {
+    req.extensions_mut()
+        .insert(ResolvedCommit(commit.to_string()));
+}
+
+/// Middleware assigning an ID to every request, either taken from the `X-Request-Id` header sent
+/// by the client or generated by us. The ID is echoed back in the response and the request is
+/// handled in a `request` span holding it, so that, when logging as JSON, it's included in every
+/// line logged while handling the request as well as in the access log record written for it.
+pub struct RequestLog {
+    format: LogFormat,
+}
+
+impl RequestLog {
+    pub fn new(format: LogFormat) -> RequestLog {
+        RequestLog { format }
+    }
+}
+
+impl<S, B> Transform<S> for RequestLog
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLogMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestLogMiddleware {
+            service,
+            format: self.format,
+        })
+    }
+}
+
+pub struct RequestLogMiddleware<S> {
+    service: S,
+    format: LogFormat,
+}
+
+impl<S, B> Service for RequestLogMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+            .map(String::from)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+        let format = self.format;
+        let span = info_span!("request", request_id = request_id.as_str());
+        let fut = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                let mut res = fut.await?;
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+
+                if format == LogFormat::Json {
+                    log_access(&res, &request_id, started);
+                }
+
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+fn log_access<B>(res: &ServiceResponse<B>, request_id: &str, started: Instant) {
+    let req = res.request();
+    let extensions = req.extensions();
+    let match_info = req.match_info();
+
+    info!(
+        target: ACCESS_LOG_TARGET,
+        request_id = request_id,
+        method = req.method().as_str(),
+        uri = req.uri().to_string(),
+        repo = match_info.get("repo"),
+        reference = extensions.get::<Reference>().map(|r| r.0.as_str()),
+        path = match_info.get("path"),
+        sha = extensions.get::<ResolvedCommit>().map(|c| c.0.as_str()),
+        status = res.status().as_u16(),
+        latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+        "{} {} {}",
+        req.method(),
+        req.path(),
+        res.status().as_u16()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    async fn respond_ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    // Answers with the line it logs, as it would be written as JSON.
+    async fn respond_with_log_line() -> HttpResponse {
+        let record = Record::builder()
+            .args(format_args!("Handling"))
+            .target("gitkv")
+            .build();
+        HttpResponse::Ok().json(json_line("now".to_string(), &record).unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn request_id_is_propagated_from_the_client() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestLog::new(LogFormat::Text))
+                .route("/", web::get().to(respond_ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+    }
+
+    #[actix_rt::test]
+    async fn request_id_is_generated_when_missing() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestLog::new(LogFormat::Json))
+                .route("/", web::get().to(respond_ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let header = resp.headers().get(REQUEST_ID_HEADER).unwrap();
+
+        assert!(uuid::Uuid::parse_str(header.to_str().unwrap()).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn request_id_is_in_the_lines_logged_while_handling_the_request() {
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(RequestIds));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestLog::new(LogFormat::Json))
+                .route("/", web::get().to(respond_with_log_line)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .to_request();
+        let line: Value = test::read_response_json(&mut app, req).await;
+
+        assert_eq!(line["target"], "gitkv");
+        assert_eq!(line["request_id"], "abc-123");
+    }
+}
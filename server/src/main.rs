@@ -7,18 +7,81 @@ extern crate actix_web;
 extern crate log;
 extern crate env_logger;
 
+mod admin;
+mod archive;
+mod logging;
+mod schemas;
+mod streaming;
+mod telemetry;
+
 use actix::{Actor, Addr};
-use actix_web::{error, get, http, middleware, web, App, HttpServer};
-use env_logger::Env;
+use actix_web::{
+    delete, error, get,
+    http::{
+        self,
+        header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue},
+    },
+    middleware, post, put, web, App, HttpRequest, HttpResponse, HttpServer,
+};
+use admin::RepoReport;
+use archive::{ArchiveFormat, Archiver};
+use documents::Format;
+use futures::future::{self, Either};
+use futures::stream::{self, Stream, TryStreamExt};
+use git::{SignatureStatus, TreeFile};
 use handlers::{
-    CatFile, CatFileResponse, GitRepos, LsDir, LsDirResponse, ResolveRef, ResolveRefResponse,
+    BlameFile, BlameFileResponse, BlobContent, BlobPart, ByteRange, CatFile, CatFileError,
+    CatFileResponse, CatFiles, CatFilesResponse, CreateBranch, CreateTag, DeleteRef,
+    DeleteRefResponse, FindFile, FindFileResponse, GitRepos, ListRefs, ListRefsResponse, LsDir,
+    LsDirResponse, MoveBranch, ReadTree, ReadTreeResponse, ResolveRef, ResolveRefResponse,
+    Resolved, VerifyCommit, VerifyCommitResponse, WriteRefError, WriteRefResponse,
 };
+use logging::{LogFormat, RequestLog};
+use serde_json::{Map, Value};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tracing::{info_span, instrument, Instrument, Span};
 
 const DEFAULT_PORT: &str = "7791";
 const DEFAULT_HOST: &str = "localhost";
 const DEFAULT_REPO_ROOT: &str = "./";
 const DEFAULT_REFERENCE: &str = "origin/master";
+const DEFAULT_LOG_FORMAT: &str = "text";
+const DEFAULT_SHUTDOWN_TIMEOUT: &str = "30";
+const DEFAULT_REPO_DEPTH: &str = "1";
+
+// The endpoints following the repo in our routes. Repo keys can be namespaced (ie. `team/service`)
+// so the routes match the shortest repo key followed by an endpoint, which means that a namespaced
+// key can't contain a segment named like one of them.
+const ENDPOINTS: &[&str] = &[
+    "archive",
+    "batch-cat",
+    "blame",
+    "branches",
+    "cat",
+    "ls",
+    "merged",
+    "refs",
+    "resolve",
+    "tags",
+];
+
+// The header telling which commit the reference of a read resolved to. Reading with that SHA as the
+// reference is guaranteed to read from the same commit.
+const COMMIT_HEADER: &str = "x-gitkv-commit";
+
+// The headers telling whether the commit a reference resolved to is signed by a trusted key, and
+// by which one.
+const SIGNATURE_HEADER: &str = "x-gitkv-signature";
+const SIGNER_HEADER: &str = "x-gitkv-signer";
+
+// Query parameters starting with this are variables for rendering templates, ie. `var.env=prod`.
+const TEMPLATE_VAR_PREFIX: &str = "var.";
+
+// The default format of `middleware::Logger`, we append the request ID to it.
+const ACCESS_LOG_FORMAT: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#;
 
 #[derive(Deserialize)]
 pub struct PathParams {
@@ -34,31 +97,397 @@ pub struct RepoPathParams {
 #[derive(Deserialize)]
 pub struct QueryParams {
     pub reference: Option<String>,
+    /// Read at the last commit the reference had at this time, ie. `2020-09-01T12:00:00Z`.
+    pub at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LsQueryParams {
+    pub reference: Option<String>,
+    /// Read at the last commit the reference had at this time, ie. `2020-09-01T12:00:00Z`.
+    pub at: Option<String>,
+    /// Descend into the submodules along the path.
+    #[serde(default)]
+    pub submodules: bool,
+    /// Follow the symlinks along the path within the tree.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CatQueryParams {
+    pub reference: Option<String>,
+    /// Read at the last commit the reference had at this time, ie. `2020-09-01T12:00:00Z`.
+    pub at: Option<String>,
+    /// Serve the file, parsed according to its extension, in this format instead.
+    pub format: Option<Format>,
+    /// Serve only the value at this JSON pointer (ie. `/database/host`) of the parsed file.
+    pub pointer: Option<String>,
+    /// Render the file as a template, with the `var.NAME` query parameters and the files in
+    /// `context` as its variables.
+    #[serde(default)]
+    pub render: bool,
+    /// Comma separated paths of the structured files merged into the context of the template.
+    pub context: Option<String>,
+    /// Serve Git LFS pointers as they are stored instead of the file they point to.
+    #[serde(default)]
+    pub raw: bool,
+    /// Descend into the submodules along the path.
+    #[serde(default)]
+    pub submodules: bool,
+    /// Follow the symlinks along the path within the tree.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveQueryParams {
+    pub reference: Option<String>,
+    /// Read at the last commit the reference had at this time, ie. `2020-09-01T12:00:00Z`.
+    pub at: Option<String>,
+    pub format: Option<ArchiveFormat>,
+}
+
+#[derive(Deserialize)]
+pub struct MergedQueryParams {
+    pub reference: Option<String>,
+    /// Read at the last commit the reference had at this time, ie. `2020-09-01T12:00:00Z`.
+    pub at: Option<String>,
+    /// Comma separated paths of the files to merge, each one overriding the ones before it.
+    pub paths: String,
+    /// Serve the file each value came from along with the merged document.
+    #[serde(default)]
+    pub annotate: bool,
+    pub format: Option<Format>,
+}
+
+#[derive(Deserialize)]
+pub struct BatchCatRequest {
+    pub reference: Option<String>,
+    /// Read at the last commit the reference had at this time, ie. `2020-09-01T12:00:00Z`.
+    pub at: Option<String>,
+    pub paths: Vec<PathBuf>,
+}
+
+/// The files read by a batch, in the order they were asked for, along with the commit the
+/// reference resolved to.
+#[derive(Serialize)]
+pub struct BatchCatResponse {
+    pub commit: String,
+    pub files: Vec<BatchCatFile>,
+}
+
+#[derive(Serialize)]
+pub struct BatchCatFile {
+    pub path: PathBuf,
+    /// Base64 encoded, as files can hold any bytes.
+    pub content: String,
+}
+
+/// The lines of a file grouped by the commit that last changed them, along with the commit the
+/// reference resolved to.
+#[derive(Serialize)]
+pub struct BlameResponse {
+    pub commit: String,
+    pub ranges: Vec<BlameRange>,
+}
+
+/// Consecutive lines, counting from 1 and inclusive of both ends, last changed by the same commit.
+#[derive(Serialize)]
+pub struct BlameRange {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub commit: String,
+    pub author: Person,
+    /// When the commit was authored, in seconds since the Unix epoch.
+    pub timestamp: i64,
+}
+
+/// Who authored a commit or created a tag.
+#[derive(Deserialize, Serialize)]
+pub struct Person {
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefsQueryParams {
+    pub kind: Option<RefKind>,
+    /// Only list the references whose name starts with this, ie. `release/`.
+    pub prefix: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RefKind {
+    Branch,
+    Remote,
+    Tag,
+}
+
+impl From<git::RefKind> for RefKind {
+    fn from(kind: git::RefKind) -> RefKind {
+        match kind {
+            git::RefKind::Branch => RefKind::Branch,
+            git::RefKind::Remote => RefKind::Remote,
+            git::RefKind::Tag => RefKind::Tag,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RefResponse {
+    pub name: String,
+    pub kind: RefKind,
+    /// The SHA of the object the reference points to, the tagged one for annotated tags.
+    pub target: String,
+    /// Only for annotated tags.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<TagResponse>,
+}
+
+#[derive(Serialize)]
+pub struct TagResponse {
+    /// The SHA of the tag itself, which deleting the tag expects as its old target.
+    pub id: String,
+    pub tagger: Option<Person>,
+    /// When the tag was created, in seconds since the Unix epoch.
+    pub timestamp: Option<i64>,
+    pub message: Option<String>,
+}
+
+impl From<git::RefEntry> for RefResponse {
+    fn from(entry: git::RefEntry) -> RefResponse {
+        RefResponse {
+            name: entry.name,
+            kind: entry.kind.into(),
+            target: entry.target,
+            tag: entry.tag.map(|tag| TagResponse {
+                id: tag.id,
+                tagger: tag
+                    .tagger_name
+                    .zip(tag.tagger_email)
+                    .map(|(name, email)| Person { name, email }),
+                timestamp: tag.timestamp,
+                message: tag.message,
+            }),
+        }
+    }
+}
+
+/// A tag to create, annotated when it has a message.
+#[derive(Deserialize)]
+pub struct CreateTagRequest {
+    pub name: String,
+    /// The reference or SHA of the commit to tag.
+    pub target: String,
+    pub message: Option<String>,
+    /// Who tags the commit, the identity in the config of the repo if not given.
+    pub tagger: Option<Person>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateBranchRequest {
+    pub name: String,
+    /// The reference or SHA of the commit the branch starts at.
+    pub target: String,
+}
+
+/// Moves a branch to a new target, as long as it's still at its old target.
+#[derive(Deserialize)]
+pub struct MoveBranchRequest {
+    pub target: String,
+    /// The full SHA of the commit the branch is expected to be at.
+    pub old_target: String,
+    /// Move it even if the new target doesn't descend from the old one.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteRefQueryParams {
+    /// The full SHA the reference is expected to point to.
+    pub old_target: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefPathParams {
+    pub repo: String,
+    pub name: String,
 }
 
 pub struct AppState {
     pub git_repos: Addr<GitRepos>,
+    pub repo_reports: Arc<Vec<RepoReport>>,
+    /// The most bytes of a file served by a single `cat` request.
+    pub max_blob_size: Option<u64>,
+    /// Whether references can be created and moved, which is refused otherwise.
+    pub allow_writes: bool,
+    /// Whether keys are trusted to sign commits, without which signatures aren't checked.
+    pub verify_signatures: bool,
+    /// Only serve content from commits signed by a trusted key, or tagged by a tag that is.
+    pub require_signed: bool,
+    /// The schema mappings of the commits last read, shared by every worker.
+    pub schema_mappings: Arc<schemas::Mappings>,
+}
+
+/// Whether references can be written, and how.
+pub struct WriteSettings {
+    /// Allow creating and moving references, which is refused otherwise.
+    pub allow: bool,
+    /// The key to sign the annotated tags created with.
+    pub signing_key: Option<git::SigningKey>,
+}
+
+/// Where to look for the repositories to serve, and how picky to be about them.
+pub struct RepoSettings<'a> {
+    pub root: &'a Path,
+    pub depth: usize,
+    pub mappings: Vec<(String, PathBuf)>,
+    /// The repos serving submodules by their URL, besides the ones with a remote at that URL.
+    pub submodules: Vec<(String, String)>,
+    /// Refuse to start if any directory examined couldn't be opened.
+    pub strict: bool,
+    /// The keys trusted to sign commits and tags.
+    pub keyring: git::Keyring,
+    /// Refuse to serve content from commits that aren't signed by a key of the keyring.
+    pub require_signed: bool,
+}
+
+/// How the server is run, as given on the command line.
+pub struct ServerSettings<'a> {
+    pub host: &'a str,
+    pub port: &'a str,
+    pub repos: RepoSettings<'a>,
+    pub log_format: LogFormat,
+    /// How long requests in flight are waited for when stopping, in seconds.
+    pub shutdown_timeout: u64,
+    /// The most bytes of a file served by a single `cat` request.
+    pub max_blob_size: Option<u64>,
+    pub writes: WriteSettings,
 }
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::from_env(Env::default().default_filter_or("gitkv=info")).init();
-
     let args = parse_args().get_matches();
+    let settings = server_settings(&args);
+
+    logging::init(settings.log_format);
+
+    let tracer_provider = telemetry::init(args.value_of("otlp-endpoint"))?;
+
+    let result = run_server(settings).await;
+
+    if let Some(provider) = tracer_provider {
+        if let Err(err) = provider.shutdown() {
+            warn!("Failed to flush traces: {}", err);
+        }
+    }
 
-    let host = args.value_of("host").unwrap_or(DEFAULT_HOST);
-    let port = args.value_of("port").unwrap_or(DEFAULT_PORT);
-    let repo_root = Path::new(args.value_of("repo-root").unwrap_or(DEFAULT_REPO_ROOT));
+    result
+}
 
-    run_server(host, port, repo_root).await
+// Reads the settings of the server from the command line, exiting when they're invalid.
+fn server_settings<'a>(args: &'a clap::ArgMatches) -> ServerSettings<'a> {
+    ServerSettings {
+        host: args.value_of("host").unwrap_or(DEFAULT_HOST),
+        port: args.value_of("port").unwrap_or(DEFAULT_PORT),
+        repos: RepoSettings {
+            root: Path::new(args.value_of("repo-root").unwrap_or(DEFAULT_REPO_ROOT)),
+            depth: value_t!(args, "repo-depth", usize).unwrap_or_else(|e| e.exit()),
+            mappings: args
+                .values_of("repo")
+                .map(|values| values.filter_map(parse_repo_mapping).collect())
+                .unwrap_or_default(),
+            submodules: args
+                .values_of("submodule")
+                .map(|values| values.filter_map(parse_submodule_mapping).collect())
+                .unwrap_or_default(),
+            strict: args.is_present("strict"),
+            keyring: git::Keyring {
+                gpg: args.value_of("gpg-keyring").map(PathBuf::from),
+                ssh_allowed_signers: args.value_of("ssh-allowed-signers").map(PathBuf::from),
+            },
+            require_signed: args.is_present("require-signed"),
+        },
+        log_format: value_t!(args, "log-format", LogFormat).unwrap_or_else(|e| e.exit()),
+        shutdown_timeout: value_t!(args, "shutdown-timeout", u64).unwrap_or_else(|e| e.exit()),
+        max_blob_size: args
+            .value_of("max-blob-size")
+            .map(|_| value_t!(args, "max-blob-size", u64).unwrap_or_else(|e| e.exit())),
+        writes: WriteSettings {
+            allow: args.is_present("allow-writes"),
+            signing_key: args
+                .value_of("gpg-signing-key")
+                .map(|key| git::SigningKey::Gpg(key.to_string()))
+                .or_else(|| {
+                    args.value_of("ssh-signing-key")
+                        .map(|key| git::SigningKey::Ssh(PathBuf::from(key)))
+                }),
+        },
+    }
 }
 
-async fn run_server(host: &str, port: &str, repo_root: &Path) -> std::io::Result<()> {
-    let repos = git::load_repos(&repo_root);
+async fn run_server(settings: ServerSettings<'_>) -> std::io::Result<()> {
+    let ServerSettings {
+        host,
+        port,
+        repos: repo_settings,
+        log_format,
+        shutdown_timeout,
+        max_blob_size,
+        writes: write_settings,
+    } = settings;
+
+    let git::LoadedRepos { repos, diagnostics } = git::load_repos(
+        repo_settings.root,
+        &repo_settings.mappings,
+        repo_settings.depth,
+    )
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string()))?;
+
+    admin::log_diagnostics(&diagnostics);
+
+    let failures = admin::failures(&diagnostics);
+    if repo_settings.strict && !failures.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Failed to open repos:\n{}", failures.join("\n")),
+        ));
+    }
+
+    if let Some(key) = repos.keys().find(|key| !is_routable(key)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Repo '{}' can't be served as its namespace contains one of {:?}",
+                key, ENDPOINTS
+            ),
+        ));
+    }
 
     info!("Loaded Git repos: {:?}", repos.keys());
 
-    let addr = GitRepos::new(repos).start();
+    let keyring = load_keyring(&repo_settings.keyring)?;
+    if repo_settings.require_signed && keyring.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--require-signed needs keys to trust, given with --gpg-keyring or --ssh-allowed-signers",
+        ));
+    }
+    let verify_signatures = !keyring.is_empty();
+    let require_signed = repo_settings.require_signed;
+    let allow_writes = write_settings.allow;
+
+    let mut git_repos = GitRepos::new(repos)
+        .with_submodules(&repo_settings.submodules)
+        .with_keyring(keyring);
+    if let Some(signing_key) = write_settings.signing_key {
+        git_repos = git_repos.with_signing_key(signing_key);
+    }
+    let addr = git_repos.start();
+    let repo_reports = Arc::new(diagnostics.iter().map(RepoReport::from).collect::<Vec<_>>());
+    let schema_mappings = Arc::new(schemas::Mappings::default());
     let listen_address = format!("{}:{}", host, port);
 
     info!("Listening on {}", listen_address);
@@ -67,31 +496,101 @@ async fn run_server(host: &str, port: &str, repo_root: &Path) -> std::io::Result
         App::new()
             .data(AppState {
                 git_repos: addr.clone(),
+                repo_reports: repo_reports.clone(),
+                max_blob_size,
+                allow_writes,
+                verify_signatures,
+                require_signed,
+                schema_mappings: schema_mappings.clone(),
             })
-            .wrap(middleware::Logger::default())
+            .wrap(RequestLog::new(log_format))
+            .wrap(middleware::Logger::new(&format!(
+                "{} %{{{}}}o",
+                ACCESS_LOG_FORMAT,
+                logging::REQUEST_ID_HEADER
+            )))
             .service(cat_file)
             .service(ls_dir)
             .service(resolve_ref)
+            .service(merged)
+            .service(batch_cat)
+            .service(archive_dir)
+            .service(blame_file)
+            .service(list_refs)
+            .service(create_tag)
+            .service(create_branch)
+            .service(move_branch)
+            .service(delete_branch)
+            .service(delete_tag)
+            .service(admin::repos)
     })
+    // On SIGTERM the server stops accepting connections and waits up to `shutdown_timeout` seconds
+    // for in-flight requests to finish before exiting. That's all actix's `shutdown_timeout` does:
+    // nothing else is tracked, as writes happen within the requests making them and Gitkv never
+    // fetches.
+    .shutdown_timeout(shutdown_timeout)
     .bind(listen_address)?
     .run()
-    .await
+    .await?;
+
+    info!("Server stopped");
+
+    Ok(())
+}
+
+// Makes the paths of the keyring absolute, as the programs checking signatures take relative ones
+// to be in their own directories, failing if they don't exist.
+fn load_keyring(keyring: &git::Keyring) -> std::io::Result<git::Keyring> {
+    let canonicalize = |path: &PathBuf| {
+        path.canonicalize()
+            .map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    };
+
+    Ok(git::Keyring {
+        gpg: keyring.gpg.as_ref().map(canonicalize).transpose()?,
+        ssh_allowed_signers: keyring
+            .ssh_allowed_signers
+            .as_ref()
+            .map(canonicalize)
+            .transpose()?,
+    })
+}
+
+// Whether the routes can tell the given repo key apart from the endpoint following it.
+fn is_routable(repo_key: &str) -> bool {
+    repo_key
+        .split(git::NAMESPACE_SEPARATOR)
+        .skip(1)
+        .all(|segment| !ENDPOINTS.contains(&segment))
 }
 
 macro_rules! not_found {
     () => {
-        |err| error::InternalError::new(err, http::StatusCode::NOT_FOUND).into()
+        |err| error::Error::from(error::InternalError::new(err, http::StatusCode::NOT_FOUND))
+    };
+}
+
+macro_rules! unprocessable {
+    () => {
+        |err| {
+            error::Error::from(error::InternalError::new(
+                err,
+                http::StatusCode::UNPROCESSABLE_ENTITY,
+            ))
+        }
     };
 }
 
-#[get("/repos/{repo}/cat/{path:.+}")]
+#[get("/repos/{repo:[^/]+(?:/[^/]+)*?}/cat/{path:.+}")]
+#[instrument(skip_all, fields(repo = %path_params.repo, path = ?path_params.path))]
 async fn cat_file(
-    (app_state, path_params, query_params): (
+    (req, app_state, path_params, query_params): (
+        HttpRequest,
         web::Data<AppState>,
         web::Path<PathParams>,
-        web::Query<QueryParams>,
+        web::Query<CatQueryParams>,
     ),
-) -> Result<web::Bytes, error::Error> {
+) -> Result<HttpResponse, error::Error> {
     let addr: Addr<GitRepos> = app_state.git_repos.clone();
     let repo_key = path_params.repo.clone();
     let path = path_params.path.clone();
@@ -101,25 +600,385 @@ async fn cat_file(
         .unwrap_or(DEFAULT_REFERENCE)
         .to_string();
 
+    logging::record_reference(&req, &reference);
+    refuse_unchecked_submodules(&app_state, query_params.submodules)?;
+    let reference =
+        reference_to_read(&app_state, &repo_key, reference, query_params.at.as_deref()).await?;
+
     // TODO return proper content type depending on the content of the blob
-    addr.send(CatFile {
-        repo_key,
-        reference,
-        path,
+    let blob = if query_params.render {
+        let context = split_paths(query_params.context.as_deref().unwrap_or_default());
+        let formats = source_formats(&context)?;
+        let vars = template_vars(&req)?;
+
+        // The template is read along with its context so that they come from the same commit.
+        let mailbox = info_span!("mailbox");
+        let mut blobs = addr
+            .send(CatFiles {
+                repo_key,
+                reference,
+                paths: Some(path).into_iter().chain(context.clone()).collect(),
+                max_size: app_state.max_blob_size,
+                span: mailbox.clone(),
+            })
+            .instrument(mailbox)
+            .await
+            .map_err(not_found!())
+            .and_then(|CatFilesResponse(resp)| resp.map_err(cat_file_error))?;
+
+        let template = blobs.value.remove(0);
+        let mut variables = documents::merge(parse_layers(&context, formats, &blobs.value)?).value;
+        if variables.is_null() {
+            variables = Value::Object(Map::new());
+        }
+        if let Some(variables) = variables.as_object_mut() {
+            variables.extend(vars);
+        }
+
+        let content = documents::render(&template, &variables).map_err(unprocessable!())?;
+        Resolved {
+            value: BlobPart {
+                size: content.len() as u64,
+                range: None,
+                content: BlobContent::Bytes(content),
+            },
+            commit: blobs.commit,
+        }
+    } else {
+        // Ranges only make sense for the file as it's stored, not once converted.
+        let range = if query_params.format.is_none() && query_params.pointer.is_none() {
+            byte_range(&req)
+        } else {
+            None
+        };
+
+        let mailbox = info_span!("mailbox");
+        addr.send(CatFile {
+            repo_key,
+            reference,
+            path,
+            range,
+            max_size: app_state.max_blob_size,
+            raw: query_params.raw,
+            submodules: query_params.submodules,
+            follow_symlinks: query_params.follow_symlinks,
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(not_found!())
+        .and_then(|CatFileResponse(resp)| resp.map_err(cat_file_error))?
+    };
+
+    logging::record_commit(&req, &blob.commit);
+
+    let BlobPart {
+        size,
+        range,
+        content,
+    } = blob.value;
+    let length = content.length();
+
+    let schemas = schemas::find_schemas(
+        &addr,
+        &app_state.schema_mappings,
+        &path_params.repo,
+        &blob.commit,
+        &path_params.path,
+        app_state.max_blob_size,
+    )
+    .await;
+
+    if let Some((first, last)) = range {
+        let mut response = HttpResponse::PartialContent();
+        // Parts of a file can't be validated, which is told rather than leaving it out.
+        if !matches!(&schemas, Ok(schema_paths) if schema_paths.is_empty()) {
+            response.header(schemas::SCHEMA_VALIDATION_HEADER, "skipped");
+        }
+        return Ok(response
+            .header(COMMIT_HEADER, blob.commit)
+            .header(http::header::ACCEPT_RANGES, "bytes")
+            .set(http::header::ContentRange(
+                http::header::ContentRangeSpec::Bytes {
+                    range: Some((first, last)),
+                    instance_length: Some(size),
+                },
+            ))
+            .no_chunking(length)
+            .streaming(blob_body(content)));
+    }
+
+    let mut response = HttpResponse::Ok();
+    response.header(COMMIT_HEADER, blob.commit.as_str());
+
+    let validation = match schemas {
+        Ok(schema_paths) if schema_paths.is_empty() => None,
+        Ok(schema_paths) => Some(
+            schemas::validate(
+                &addr,
+                &path_params.repo,
+                &blob.commit,
+                &path_params.path,
+                app_state.max_blob_size,
+                schema_paths,
+                &content,
+            )
+            .await,
+        ),
+        Err(err) => Some(Err(err)),
+    };
+    match validation {
+        Some(Ok(())) => {
+            response.header(schemas::SCHEMA_VALIDATION_HEADER, "valid");
+        }
+        Some(Err(violations)) => {
+            warn!(
+                "{} at {} doesn't match its schemas:\n{}",
+                path_params.path.display(),
+                blob.commit,
+                violations
+            );
+            response.header(schemas::SCHEMA_VALIDATION_HEADER, "invalid");
+        }
+        None => (),
+    }
+
+    if query_params.format.is_none() && query_params.pointer.is_none() {
+        return Ok(response
+            .header(http::header::ACCEPT_RANGES, "bytes")
+            .no_chunking(length)
+            .streaming(blob_body(content)));
+    }
+
+    let content = read_content(content).await?;
+
+    let source = source_format(&path_params.path)?;
+    // A single value may not be representable in the format of the file it comes from (ie. a
+    // string in TOML), so it's served as JSON unless asked otherwise.
+    let format = query_params.format.unwrap_or(Format::Json);
+
+    let body = match query_params.pointer.as_deref() {
+        None => documents::convert(&content, source, format).map_err(unprocessable!())?,
+        Some(pointer) => {
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                return Err(error::ErrorBadRequest(format!(
+                    "Invalid pointer '{}', it must start with '/'",
+                    pointer
+                )));
+            }
+
+            let mut document = documents::parse(&content, source).map_err(unprocessable!())?;
+            let value = document
+                .pointer_mut(pointer)
+                .map(Value::take)
+                .ok_or_else(|| {
+                    error::ErrorNotFound(format!(
+                        "No value at '{}' in '{}'",
+                        pointer,
+                        path_params.path.display()
+                    ))
+                })?;
+            documents::serialise(&value, format).map_err(unprocessable!())?
+        }
+    };
+
+    Ok(response.content_type(format.content_type()).body(body))
+}
+
+#[get("/repos/{repo:[^/]+(?:/[^/]+)*?}/merged")]
+#[instrument(skip_all, fields(repo = %repo_path_params.repo, paths = %query_params.paths))]
+async fn merged(
+    (req, app_state, repo_path_params, query_params): (
+        HttpRequest,
+        web::Data<AppState>,
+        web::Path<RepoPathParams>,
+        web::Query<MergedQueryParams>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let repo_key = repo_path_params.repo.clone();
+    let paths = split_paths(&query_params.paths);
+    let reference = query_params
+        .reference
+        .as_deref()
+        .unwrap_or(DEFAULT_REFERENCE)
+        .to_string();
+
+    if paths.is_empty() {
+        return Err(error::ErrorBadRequest("No paths to merge"));
+    }
+
+    let formats = source_formats(&paths)?;
+
+    logging::record_reference(&req, &reference);
+    let reference =
+        reference_to_read(&app_state, &repo_key, reference, query_params.at.as_deref()).await?;
+
+    let mailbox = info_span!("mailbox");
+    let blobs = addr
+        .send(CatFiles {
+            repo_key,
+            reference,
+            paths: paths.clone(),
+            max_size: app_state.max_blob_size,
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(not_found!())
+        .and_then(|CatFilesResponse(resp)| resp.map_err(cat_file_error))?;
+
+    logging::record_commit(&req, &blobs.commit);
+
+    let layers = parse_layers(&paths, formats, &blobs.value)?;
+    let merged = documents::merge(layers);
+    let format = query_params.format.unwrap_or(Format::Json);
+    let body = if query_params.annotate {
+        serde_json::to_value(&merged)
+            .map_err(unprocessable!())
+            .and_then(|annotated| {
+                documents::serialise(&annotated, format).map_err(unprocessable!())
+            })?
+    } else {
+        documents::serialise(&merged.value, format).map_err(unprocessable!())?
+    };
+
+    Ok(HttpResponse::Ok()
+        .header(COMMIT_HEADER, blobs.commit)
+        .content_type(format.content_type())
+        .body(body))
+}
+
+// The single range of bytes asked for in the `Range` header. A header that can't be parsed or that
+// asks for several ranges is ignored, which means serving the whole file.
+fn byte_range(req: &HttpRequest) -> Option<ByteRange> {
+    let spec = req
+        .headers()
+        .get(http::header::RANGE)?
+        .to_str()
+        .ok()?
+        .trim()
+        .strip_prefix("bytes=")?;
+
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (from, to) = spec.split_once('-')?;
+    match (from.trim(), to.trim()) {
+        ("", last) => last.parse().ok().map(ByteRange::Last),
+        (from, "") => from.parse().ok().map(ByteRange::From),
+        (from, to) => Some(ByteRange::FromTo(from.parse().ok()?, to.parse().ok()?)),
+    }
+}
+
+// Streams the content of a file, reading it on another thread as it's sent unless it was read.
+fn blob_body(content: BlobContent) -> impl Stream<Item = std::io::Result<web::Bytes>> + Unpin {
+    match content {
+        BlobContent::Bytes(bytes) => Either::Left(streaming::chunks(bytes)),
+        stored => Either::Right(streaming::written(info_span!("read_blob"), move |writer| {
+            stored.write_to(writer)
+        })),
+    }
+}
+
+// Reads the whole content of a file in memory, ie. to parse it, on a blocking thread unless it
+// was read.
+async fn read_content(content: BlobContent) -> Result<Vec<u8>, error::Error> {
+    match content {
+        BlobContent::Bytes(bytes) => Ok(bytes),
+        stored => web::block(move || stored.read())
+            .await
+            .map_err(error::ErrorInternalServerError),
+    }
+}
+
+fn cat_file_error(err: CatFileError) -> error::Error {
+    let response = match err {
+        CatFileError::NotFound(_) | CatFileError::LfsObjectMissing(_) => {
+            HttpResponse::NotFound().body(err.to_string())
+        }
+        CatFileError::TooLarge { .. } => HttpResponse::PayloadTooLarge().body(err.to_string()),
+        CatFileError::Unsatisfiable { size } => HttpResponse::RangeNotSatisfiable()
+            .set(http::header::ContentRange(
+                http::header::ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(size),
+                },
+            ))
+            .body(err.to_string()),
+    };
+
+    error::InternalError::from_response(err, response).into()
+}
+
+// Tells the format of a file to parse from its extension.
+fn source_format(path: &Path) -> Result<Format, error::Error> {
+    Format::from_path(path).ok_or_else(|| {
+        error::ErrorBadRequest(format!(
+            "Can't tell the format of '{}' from its extension",
+            path.display()
+        ))
     })
-    .await
-    .map_err(not_found!())
-    .and_then(|CatFileResponse(resp)| resp.map(web::Bytes::from).map_err(not_found!()))
 }
 
-#[get("/repos/{repo}/ls/{path:.+}")]
+fn source_formats(paths: &[PathBuf]) -> Result<Vec<Format>, error::Error> {
+    paths.iter().map(|path| source_format(path)).collect()
+}
+
+// Splits a comma separated list of paths given as a query parameter.
+fn split_paths(paths: &str) -> Vec<PathBuf> {
+    paths
+        .split(',')
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+// Parses the blobs read from `paths` into documents to merge, named after their path.
+fn parse_layers<'a>(
+    paths: &'a [PathBuf],
+    formats: Vec<Format>,
+    blobs: &[Vec<u8>],
+) -> Result<Vec<(&'a str, Value)>, error::Error> {
+    paths
+        .iter()
+        .zip(formats)
+        .zip(blobs)
+        .map(|((path, format), blob)| {
+            documents::parse(blob, format)
+                .map(|document| (path.to_str().unwrap_or_default(), document))
+                .map_err(|err| {
+                    error::ErrorUnprocessableEntity(format!("{}: {}", path.display(), err))
+                })
+        })
+        .collect()
+}
+
+// Collects the `var.NAME` query parameters as template variables.
+fn template_vars(req: &HttpRequest) -> Result<Vec<(String, Value)>, error::Error> {
+    let params = web::Query::<Vec<(String, String)>>::from_query(req.query_string())?;
+
+    Ok(params
+        .into_inner()
+        .into_iter()
+        .filter_map(|(name, value)| {
+            name.strip_prefix(TEMPLATE_VAR_PREFIX)
+                .map(|name| (name.to_string(), Value::String(value)))
+        })
+        .collect())
+}
+
+#[get("/repos/{repo:[^/]+(?:/[^/]+)*?}/ls/{path:.+}")]
+#[instrument(skip_all, fields(repo = %path_params.repo, path = ?path_params.path))]
 async fn ls_dir(
-    (app_state, path_params, query_params): (
+    (req, app_state, path_params, query_params): (
+        HttpRequest,
         web::Data<AppState>,
         web::Path<PathParams>,
-        web::Query<QueryParams>,
+        web::Query<LsQueryParams>,
     ),
-) -> Result<String, error::Error> {
+) -> Result<HttpResponse, error::Error> {
     let addr: Addr<GitRepos> = app_state.git_repos.clone();
     let repo_key = path_params.repo.clone();
     let path = path_params.path.clone();
@@ -129,70 +988,747 @@ async fn ls_dir(
         .unwrap_or(DEFAULT_REFERENCE)
         .to_string();
 
+    logging::record_reference(&req, &reference);
+    refuse_unchecked_submodules(&app_state, query_params.submodules)?;
+    let reference =
+        reference_to_read(&app_state, &repo_key, reference, query_params.at.as_deref()).await?;
+
+    let mailbox = info_span!("mailbox");
     addr.send(LsDir {
         repo_key,
         reference,
         path,
+        submodules: query_params.submodules,
+        follow_symlinks: query_params.follow_symlinks,
+        span: mailbox.clone(),
     })
+    .instrument(mailbox)
     .await
     .map_err(not_found!())
     .and_then(|LsDirResponse(resp)| {
-        resp.map_err(not_found!())
-            .and_then(|children| serde_json::to_string(&children).map_err(not_found!()))
+        resp.map_err(not_found!()).and_then(|children| {
+            logging::record_commit(&req, &children.commit);
+            serde_json::to_string(&children.value)
+                .map(|body| {
+                    HttpResponse::Ok()
+                        .header(COMMIT_HEADER, children.commit)
+                        .content_type("text/plain; charset=utf-8")
+                        .body(body)
+                })
+                .map_err(not_found!())
+        })
     })
 }
 
-#[get("/repos/{repo}/resolve")]
-async fn resolve_ref(
-    (app_state, repo_path_params, query_params): (
+#[post("/repos/{repo:[^/]+(?:/[^/]+)*?}/batch-cat")]
+#[instrument(skip_all, fields(repo = %repo_path_params.repo, paths = batch.paths.len()))]
+async fn batch_cat(
+    (req, app_state, repo_path_params, batch): (
+        HttpRequest,
         web::Data<AppState>,
         web::Path<RepoPathParams>,
-        web::Query<QueryParams>,
+        web::Json<BatchCatRequest>,
     ),
-) -> Result<String, error::Error> {
+) -> Result<HttpResponse, error::Error> {
     let addr: Addr<GitRepos> = app_state.git_repos.clone();
     let repo_key = repo_path_params.repo.clone();
+    let BatchCatRequest {
+        reference,
+        at,
+        paths,
+    } = batch.into_inner();
+    let reference = reference.unwrap_or_else(|| DEFAULT_REFERENCE.to_string());
+
+    if paths.is_empty() {
+        return Err(error::ErrorBadRequest("No paths to read"));
+    }
+
+    logging::record_reference(&req, &reference);
+    let reference = reference_to_read(&app_state, &repo_key, reference, at.as_deref()).await?;
+
+    let mailbox = info_span!("mailbox");
+    let blobs = addr
+        .send(CatFiles {
+            repo_key,
+            reference,
+            paths: paths.clone(),
+            max_size: app_state.max_blob_size,
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(not_found!())
+        .and_then(|CatFilesResponse(resp)| resp.map_err(cat_file_error))?;
+
+    logging::record_commit(&req, &blobs.commit);
+
+    Ok(HttpResponse::Ok()
+        .header(COMMIT_HEADER, blobs.commit.as_str())
+        .json(BatchCatResponse {
+            commit: blobs.commit,
+            files: paths
+                .into_iter()
+                .zip(blobs.value)
+                .map(|(path, blob)| BatchCatFile {
+                    path,
+                    content: base64::encode(blob),
+                })
+                .collect(),
+        }))
+}
+
+#[get("/repos/{repo:[^/]+(?:/[^/]+)*?}/archive/{path:.+}")]
+#[instrument(skip_all, fields(repo = %path_params.repo, path = ?path_params.path))]
+async fn archive_dir(
+    (req, app_state, path_params, query_params): (
+        HttpRequest,
+        web::Data<AppState>,
+        web::Path<PathParams>,
+        web::Query<ArchiveQueryParams>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let repo_key = path_params.repo.clone();
+    let path = path_params.path.clone();
     let reference = query_params
         .reference
         .as_deref()
         .unwrap_or(DEFAULT_REFERENCE)
         .to_string();
+    let format = query_params.format.unwrap_or(ArchiveFormat::TarGz);
 
-    addr.send(ResolveRef {
+    logging::record_reference(&req, &reference);
+    let reference =
+        reference_to_read(&app_state, &repo_key, reference, query_params.at.as_deref()).await?;
+
+    let mailbox = info_span!("mailbox");
+    let tree = addr
+        .send(ReadTree {
+            repo_key: repo_key.clone(),
+            reference,
+            path,
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(not_found!())
+        .and_then(|ReadTreeResponse(resp)| resp.map_err(not_found!()))?;
+
+    logging::record_commit(&req, &tree.commit);
+
+    let commit = tree.commit;
+    let files = tree.value;
+
+    // Files are read as they are archived, so their sizes are checked beforehand rather than
+    // failing halfway through a response that's already started.
+    if let Some(max_size) = app_state.max_blob_size {
+        if let Some(file) = files.iter().find(|file| file.size > max_size) {
+            return Err(cat_file_error(CatFileError::TooLarge {
+                path: path_params.path.join(&file.path),
+                size: file.size,
+                max_size,
+            }));
+        }
+    }
+
+    let name = path_params
+        .path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("archive");
+    let disposition = archive_disposition(&format!("{}.{}", name, format.extension()));
+
+    let tree = ArchivedTree {
+        addr,
         repo_key,
-        reference,
-    })
-    .await
-    .map_err(not_found!())
-    .and_then(|ResolveRefResponse(resp)| resp.map_err(not_found!()))
+        commit: commit.clone(),
+        directory: path_params.path.clone(),
+        max_size: app_state.max_blob_size,
+        span: info_span!("archive"),
+    };
+    let body = archive_body(tree, files, format);
+
+    Ok(HttpResponse::Ok()
+        .header(COMMIT_HEADER, commit)
+        .content_type(format.content_type())
+        .set(disposition)
+        .streaming(body))
 }
 
-fn parse_args<'a, 'b>() -> clap::App<'a, 'b> {
-    clap::App::new(crate_name!())
-        .version(crate_version!())
-        // FIXME: Switch back to `crate_authors` macro once deprecation warnings are fixed in
-        // stable warnings.
-        //
-        // .author(crate_authors!("\n"))
-        .author("Intent HQ")
-        .about(crate_description!())
-        .arg(
-            clap::Arg::with_name("port")
-                .short("p")
-                .long("port")
-                .takes_value(true)
-                .value_name("PORT")
-                .default_value(DEFAULT_PORT)
-                .help("port to listen to"),
-        )
-        .arg(
-            clap::Arg::with_name("host")
-                .short("h")
-                .long("host")
-                .takes_value(true)
-                .value_name("HOST")
-                .default_value(DEFAULT_HOST)
-                .help("host to listen to"),
+// The tree whose files are archived, which are read from the commit it was listed from.
+struct ArchivedTree {
+    addr: Addr<GitRepos>,
+    repo_key: String,
+    commit: String,
+    directory: PathBuf,
+    max_size: Option<u64>,
+    span: Span,
+}
+
+impl ArchivedTree {
+    async fn read(&self, file: &TreeFile) -> std::io::Result<Vec<u8>> {
+        let mailbox = info_span!(parent: &self.span, "mailbox");
+        let path = self.directory.join(&file.path);
+        let found = self
+            .addr
+            .send(FindFile {
+                repo_key: self.repo_key.clone(),
+                commit: self.commit.clone(),
+                path: path.clone(),
+                max_size: self.max_size,
+                span: mailbox.clone(),
+            })
+            .instrument(mailbox)
+            .await;
+
+        match found {
+            Ok(FindFileResponse(Ok(Some(content)))) => Ok(content),
+            Ok(FindFileResponse(Ok(None))) => Err(std::io::Error::other(format!(
+                "'{}' is gone",
+                path.display()
+            ))),
+            Ok(FindFileResponse(Err(err))) => Err(std::io::Error::other(err.to_string())),
+            Err(err) => Err(std::io::Error::other(err.to_string())),
+        }
+    }
+}
+
+// Archives the files one at a time as the client reads the archive. Each of them is read from the
+// actor and then appended on a blocking thread, so that no thread waits for the client. Failing to
+// archive a file aborts the response so that a truncated archive isn't taken for a whole one.
+fn archive_body(
+    tree: ArchivedTree,
+    files: Vec<TreeFile>,
+    format: ArchiveFormat,
+) -> impl Stream<Item = std::io::Result<web::Bytes>> + Unpin {
+    let tree = Rc::new(tree);
+    let archived = stream::unfold(
+        (files.into_iter(), Some(Archiver::new(format))),
+        move |(mut files, archiver)| {
+            let tree = tree.clone();
+            async move {
+                let mut archiver = archiver?;
+                let written = match files.next() {
+                    Some(file) => match tree.read(&file).await {
+                        Ok(content) => web::block(move || {
+                            archiver.append(&file, &content)?;
+                            Ok(archiver)
+                        })
+                        .await
+                        .map(|archiver| (archiver.take(), Some(archiver))),
+                        Err(err) => Err(error::BlockingError::Error(err)),
+                    },
+                    None => web::block(move || archiver.finish())
+                        .await
+                        .map(|rest| (rest, None)),
+                };
+
+                match written {
+                    Ok((bytes, archiver)) => Some((Ok(web::Bytes::from(bytes)), (files, archiver))),
+                    Err(err) => {
+                        warn!("Failed to write the archive: {}", err);
+                        Some((Err(std::io::Error::other(err.to_string())), (files, None)))
+                    }
+                }
+            }
+        },
+    );
+
+    Box::pin(archived.try_filter(|bytes| future::ready(!bytes.is_empty())))
+}
+
+// Names the archive to download. Names that aren't plain ASCII are given as is in the extended
+// parameter, and with their other characters replaced in the one older clients read.
+fn archive_disposition(filename: &str) -> ContentDisposition {
+    let ascii = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let mut parameters = vec![DispositionParam::Filename(ascii.clone())];
+
+    if ascii != filename {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.as_bytes().to_vec(),
+        }));
+    }
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters,
+    }
+}
+
+#[get("/repos/{repo:[^/]+(?:/[^/]+)*?}/blame/{path:.+}")]
+#[instrument(skip_all, fields(repo = %path_params.repo, path = ?path_params.path))]
+async fn blame_file(
+    (req, app_state, path_params, query_params): (
+        HttpRequest,
+        web::Data<AppState>,
+        web::Path<PathParams>,
+        web::Query<QueryParams>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let repo_key = path_params.repo.clone();
+    let path = path_params.path.clone();
+    let reference = query_params
+        .reference
+        .as_deref()
+        .unwrap_or(DEFAULT_REFERENCE)
+        .to_string();
+
+    logging::record_reference(&req, &reference);
+    let reference =
+        reference_to_read(&app_state, &repo_key, reference, query_params.at.as_deref()).await?;
+
+    let mailbox = info_span!("mailbox");
+    let blame = addr
+        .send(BlameFile {
+            repo_key,
+            reference,
+            path,
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(not_found!())
+        .and_then(|BlameFileResponse(resp)| resp.map_err(not_found!()))?;
+
+    logging::record_commit(&req, &blame.commit);
+
+    Ok(HttpResponse::Ok()
+        .header(COMMIT_HEADER, blame.commit.as_str())
+        .json(BlameResponse {
+            commit: blame.commit,
+            ranges: blame
+                .value
+                .into_iter()
+                .map(|hunk| BlameRange {
+                    start_line: hunk.start_line,
+                    end_line: hunk.start_line + hunk.lines - 1,
+                    commit: hunk.commit,
+                    author: Person {
+                        name: hunk.author_name,
+                        email: hunk.author_email,
+                    },
+                    timestamp: hunk.timestamp,
+                })
+                .collect(),
+        }))
+}
+
+#[get("/repos/{repo:[^/]+(?:/[^/]+)*?}/refs")]
+#[instrument(skip_all, fields(repo = %repo_path_params.repo))]
+async fn list_refs(
+    (app_state, repo_path_params, query_params): (
+        web::Data<AppState>,
+        web::Path<RepoPathParams>,
+        web::Query<RefsQueryParams>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let repo_key = repo_path_params.repo.clone();
+    let prefix = query_params.prefix.as_deref().unwrap_or_default();
+
+    let mailbox = info_span!("mailbox");
+    let refs = addr
+        .send(ListRefs {
+            repo_key,
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(not_found!())
+        .and_then(|ListRefsResponse(resp)| resp.map_err(not_found!()))?;
+
+    Ok(HttpResponse::Ok().json(
+        refs.into_iter()
+            .map(RefResponse::from)
+            .filter(|entry| query_params.kind.is_none_or(|kind| kind == entry.kind))
+            .filter(|entry| entry.name.starts_with(prefix))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[post("/repos/{repo:[^/]+(?:/[^/]+)*?}/tags")]
+#[instrument(skip_all, fields(repo = %repo_path_params.repo, tag = %tag.name))]
+async fn create_tag(
+    (app_state, repo_path_params, tag): (
+        web::Data<AppState>,
+        web::Path<RepoPathParams>,
+        web::Json<CreateTagRequest>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    check_writes_allowed(&app_state)?;
+
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let CreateTagRequest {
+        name,
+        target,
+        message,
+        tagger,
+    } = tag.into_inner();
+
+    let mailbox = info_span!("mailbox");
+    addr.send(CreateTag {
+        repo_key: repo_path_params.repo.clone(),
+        name,
+        target,
+        message,
+        tagger: tagger.map(|tagger| (tagger.name, tagger.email)),
+        span: mailbox.clone(),
+    })
+    .instrument(mailbox)
+    .await
+    .map_err(not_found!())
+    .and_then(|WriteRefResponse(resp)| resp.map_err(write_ref_error))
+    .map(|entry| HttpResponse::Created().json(RefResponse::from(entry)))
+}
+
+#[post("/repos/{repo:[^/]+(?:/[^/]+)*?}/branches")]
+#[instrument(skip_all, fields(repo = %repo_path_params.repo, branch = %branch.name))]
+async fn create_branch(
+    (app_state, repo_path_params, branch): (
+        web::Data<AppState>,
+        web::Path<RepoPathParams>,
+        web::Json<CreateBranchRequest>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    check_writes_allowed(&app_state)?;
+
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let CreateBranchRequest { name, target } = branch.into_inner();
+
+    let mailbox = info_span!("mailbox");
+    addr.send(CreateBranch {
+        repo_key: repo_path_params.repo.clone(),
+        name,
+        target,
+        span: mailbox.clone(),
+    })
+    .instrument(mailbox)
+    .await
+    .map_err(not_found!())
+    .and_then(|WriteRefResponse(resp)| resp.map_err(write_ref_error))
+    .map(|entry| HttpResponse::Created().json(RefResponse::from(entry)))
+}
+
+#[put("/repos/{repo:[^/]+(?:/[^/]+)*?}/branches/{name:.+}")]
+#[instrument(skip_all, fields(repo = %path_params.repo, branch = %path_params.name))]
+async fn move_branch(
+    (app_state, path_params, branch): (
+        web::Data<AppState>,
+        web::Path<RefPathParams>,
+        web::Json<MoveBranchRequest>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    check_writes_allowed(&app_state)?;
+
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let MoveBranchRequest {
+        target,
+        old_target,
+        force,
+    } = branch.into_inner();
+
+    let mailbox = info_span!("mailbox");
+    addr.send(MoveBranch {
+        repo_key: path_params.repo.clone(),
+        name: path_params.name.clone(),
+        target,
+        old_target,
+        force,
+        span: mailbox.clone(),
+    })
+    .instrument(mailbox)
+    .await
+    .map_err(not_found!())
+    .and_then(|WriteRefResponse(resp)| resp.map_err(write_ref_error))
+    .map(|entry| HttpResponse::Ok().json(RefResponse::from(entry)))
+}
+
+#[delete("/repos/{repo:[^/]+(?:/[^/]+)*?}/branches/{name:.+}")]
+#[instrument(skip_all, fields(repo = %path_params.repo, branch = %path_params.name))]
+async fn delete_branch(
+    (app_state, path_params, query_params): (
+        web::Data<AppState>,
+        web::Path<RefPathParams>,
+        web::Query<DeleteRefQueryParams>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    delete_ref(
+        &app_state,
+        &path_params,
+        git::RefKind::Branch,
+        &query_params,
+    )
+    .await
+}
+
+#[delete("/repos/{repo:[^/]+(?:/[^/]+)*?}/tags/{name:.+}")]
+#[instrument(skip_all, fields(repo = %path_params.repo, tag = %path_params.name))]
+async fn delete_tag(
+    (app_state, path_params, query_params): (
+        web::Data<AppState>,
+        web::Path<RefPathParams>,
+        web::Query<DeleteRefQueryParams>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    delete_ref(&app_state, &path_params, git::RefKind::Tag, &query_params).await
+}
+
+async fn delete_ref(
+    app_state: &AppState,
+    path_params: &RefPathParams,
+    kind: git::RefKind,
+    query_params: &DeleteRefQueryParams,
+) -> Result<HttpResponse, error::Error> {
+    check_writes_allowed(app_state)?;
+
+    let mailbox = info_span!("mailbox");
+    app_state
+        .git_repos
+        .send(DeleteRef {
+            repo_key: path_params.repo.clone(),
+            kind,
+            name: path_params.name.clone(),
+            old_target: query_params.old_target.clone(),
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(not_found!())
+        .and_then(|DeleteRefResponse(resp)| resp.map_err(write_ref_error))
+        .map(|()| HttpResponse::NoContent().finish())
+}
+
+fn check_writes_allowed(app_state: &AppState) -> Result<(), error::Error> {
+    if app_state.allow_writes {
+        Ok(())
+    } else {
+        Err(error::ErrorForbidden(
+            "Writes are disabled, start gitkv with --allow-writes to enable them",
+        ))
+    }
+}
+
+fn write_ref_error(err: WriteRefError) -> error::Error {
+    let response = match err {
+        WriteRefError::NotFound(_) => HttpResponse::NotFound(),
+        WriteRefError::Invalid(_) => HttpResponse::BadRequest(),
+        WriteRefError::Conflict(_) => HttpResponse::Conflict(),
+        WriteRefError::Protected(_) => HttpResponse::Forbidden(),
+        WriteRefError::Failed(_) => HttpResponse::InternalServerError(),
+    }
+    .body(err.to_string());
+
+    error::InternalError::from_response(err, response).into()
+}
+
+#[get("/repos/{repo:[^/]+(?:/[^/]+)*?}/resolve")]
+#[instrument(skip_all, fields(repo = %repo_path_params.repo))]
+async fn resolve_ref(
+    (req, app_state, repo_path_params, query_params): (
+        HttpRequest,
+        web::Data<AppState>,
+        web::Path<RepoPathParams>,
+        web::Query<QueryParams>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    let addr: Addr<GitRepos> = app_state.git_repos.clone();
+    let repo_key = repo_path_params.repo.clone();
+    let reference = query_params
+        .reference
+        .as_deref()
+        .unwrap_or(DEFAULT_REFERENCE)
+        .to_string();
+
+    logging::record_reference(&req, &reference);
+
+    let at = query_params.at.as_deref().map(parse_time).transpose()?;
+    let commit = resolve(&addr, &repo_key, reference.clone(), at).await?;
+    logging::record_commit(&req, &commit);
+
+    let mut response = HttpResponse::Ok();
+    if !app_state.verify_signatures {
+        return Ok(response.body(commit));
+    }
+
+    // The commit was resolved, so a signature that can't be checked doesn't fail the request.
+    let status = verify_commit(&addr, &repo_key, reference, &commit)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("Can't verify the signature of commit {}: {}", commit, err);
+            SignatureStatus::Unverified
+        });
+    match status {
+        SignatureStatus::Unsigned => response.header(SIGNATURE_HEADER, "unsigned"),
+        SignatureStatus::Unverified => response.header(SIGNATURE_HEADER, "unverified"),
+        SignatureStatus::Verified { signer } => response
+            .header(SIGNATURE_HEADER, "verified")
+            .header(SIGNER_HEADER, signer),
+    };
+
+    Ok(response.body(commit))
+}
+
+// Pins a read to a commit when it has to be: the one the reference had at the time given in `at`,
+// and one signed by a trusted key when only those are served, so that the read that follows is
+// from the commit that was checked. The reference is left as is otherwise.
+async fn reference_to_read(
+    app_state: &AppState,
+    repo_key: &str,
+    reference: String,
+    at: Option<&str>,
+) -> Result<String, error::Error> {
+    let at = at.map(parse_time).transpose()?;
+    if at.is_none() && !app_state.require_signed {
+        return Ok(reference);
+    }
+
+    let addr = &app_state.git_repos;
+    let commit = resolve(addr, repo_key, reference.clone(), at).await?;
+    if app_state.require_signed {
+        match verify_commit(addr, repo_key, reference, &commit).await? {
+            SignatureStatus::Verified { .. } => {}
+            SignatureStatus::Unsigned => {
+                return Err(error::ErrorForbidden(format!(
+                    "Commit {} isn't signed, only signed commits are served",
+                    commit
+                )))
+            }
+            SignatureStatus::Unverified => {
+                return Err(error::ErrorForbidden(format!(
+                    "Commit {} isn't signed by a trusted key, only signed commits are served",
+                    commit
+                )))
+            }
+        }
+    }
+
+    Ok(commit)
+}
+
+// Refuses to descend into submodules when only signed commits are served, as only the commit the
+// reference resolves to is checked, not the ones submodules are pinned to.
+fn refuse_unchecked_submodules(app_state: &AppState, submodules: bool) -> Result<(), error::Error> {
+    if submodules && app_state.require_signed {
+        return Err(error::ErrorForbidden(
+            "Submodules aren't served when only signed commits are, as the commits they are pinned to aren't checked",
+        ));
+    }
+    Ok(())
+}
+
+async fn resolve(
+    addr: &Addr<GitRepos>,
+    repo_key: &str,
+    reference: String,
+    at: Option<i64>,
+) -> Result<String, error::Error> {
+    let mailbox = info_span!("mailbox");
+    addr.send(ResolveRef {
+        repo_key: repo_key.to_string(),
+        reference,
+        at,
+        span: mailbox.clone(),
+    })
+    .instrument(mailbox)
+    .await
+    .map_err(not_found!())
+    .and_then(|ResolveRefResponse(resp)| resp.map_err(not_found!()))
+}
+
+async fn verify_commit(
+    addr: &Addr<GitRepos>,
+    repo_key: &str,
+    reference: String,
+    commit: &str,
+) -> Result<SignatureStatus, error::Error> {
+    let mailbox = info_span!("mailbox");
+    addr.send(VerifyCommit {
+        repo_key: repo_key.to_string(),
+        reference,
+        commit: commit.to_string(),
+        span: mailbox.clone(),
+    })
+    .instrument(mailbox)
+    .await
+    .map_err(error::ErrorInternalServerError)
+    .and_then(|VerifyCommitResponse(resp)| resp.map_err(error::ErrorInternalServerError))
+}
+
+// Parses an RFC 3339 time in UTC, ie. `2020-09-01T12:00:00Z`, into seconds since the Unix epoch.
+fn parse_time(time: &str) -> Result<i64, error::Error> {
+    humantime::parse_rfc3339(time)
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .ok_or_else(|| {
+            error::ErrorBadRequest(format!(
+                "Invalid time '{}', expected one like 2020-09-01T12:00:00Z",
+                time
+            ))
+        })
+}
+
+// Splits a `NAME=PATH` repo mapping given on the command line.
+fn parse_repo_mapping(mapping: &str) -> Option<(String, PathBuf)> {
+    let mut parts = mapping.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(path)) if !name.is_empty() && !path.is_empty() => {
+            Some((name.to_string(), PathBuf::from(path)))
+        }
+        _ => None,
+    }
+}
+
+// Splits a `URL=NAME` submodule mapping given on the command line. URLs can hold a `=` while repo
+// names can't, so it's split at the last one.
+fn parse_submodule_mapping(mapping: &str) -> Option<(String, String)> {
+    let mut parts = mapping.rsplitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(url)) if !name.is_empty() && !url.is_empty() => {
+            Some((url.to_string(), name.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn parse_args<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::App::new(crate_name!())
+        .version(crate_version!())
+        // FIXME: Switch back to `crate_authors` macro once deprecation warnings are fixed in
+        // stable warnings.
+        //
+        // .author(crate_authors!("\n"))
+        .author("Intent HQ")
+        .about(crate_description!())
+        .arg(
+            clap::Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .takes_value(true)
+                .value_name("PORT")
+                .default_value(DEFAULT_PORT)
+                .help("port to listen to"),
+        )
+        .arg(
+            clap::Arg::with_name("host")
+                .short("h")
+                .long("host")
+                .takes_value(true)
+                .value_name("HOST")
+                .default_value(DEFAULT_HOST)
+                .help("host to listen to"),
         )
         .arg(
             clap::Arg::with_name("repo-root")
@@ -203,29 +1739,224 @@ fn parse_args<'a, 'b>() -> clap::App<'a, 'b> {
                 .default_value(DEFAULT_REPO_ROOT)
                 .help("path where the different repositories are located"),
         )
+        .arg(
+            clap::Arg::with_name("repo-depth")
+                .long("repo-depth")
+                .takes_value(true)
+                .value_name("DEPTH")
+                .default_value(DEFAULT_REPO_DEPTH)
+                .help("how many directories deep under the repo root to look for repositories"),
+        )
+        .arg(
+            clap::Arg::with_name("repo")
+                .long("repo")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=PATH")
+                .validator(|mapping| {
+                    parse_repo_mapping(&mapping)
+                        .map(|_| ())
+                        .ok_or_else(|| format!("expected NAME=PATH but got '{}'", mapping))
+                })
+                .help("serves the repository at PATH as NAME, instead of deriving it from the directory name"),
+        )
+        .arg(
+            clap::Arg::with_name("submodule")
+                .long("submodule")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("URL=NAME")
+                .validator(|mapping| {
+                    parse_submodule_mapping(&mapping)
+                        .map(|_| ())
+                        .ok_or_else(|| format!("expected URL=NAME but got '{}'", mapping))
+                })
+                .help("serves the submodules at URL with the repository NAME, when it's not one of its remotes"),
+        )
+        .arg(
+            clap::Arg::with_name("allow-writes")
+                .long("allow-writes")
+                .help("allows creating tags and branches and fast-forwarding branches"),
+        )
+        .arg(
+            clap::Arg::with_name("gpg-keyring")
+                .long("gpg-keyring")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("keyring of the GPG keys trusted to sign commits and tags, as exported by gpg --export"),
+        )
+        .arg(
+            clap::Arg::with_name("ssh-allowed-signers")
+                .long("ssh-allowed-signers")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("allowed signers file of the SSH keys trusted to sign commits and tags"),
+        )
+        .arg(
+            clap::Arg::with_name("require-signed")
+                .long("require-signed")
+                .help("only serves content from commits signed by a trusted key, or tagged by a tag that is"),
+        )
+        .arg(
+            clap::Arg::with_name("gpg-signing-key")
+                .long("gpg-signing-key")
+                .takes_value(true)
+                .value_name("KEYID")
+                .conflicts_with("ssh-signing-key")
+                .help("GPG key to sign the annotated tags created with, from the keyring of the user running gitkv"),
+        )
+        .arg(
+            clap::Arg::with_name("ssh-signing-key")
+                .long("ssh-signing-key")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("private SSH key to sign the annotated tags created with"),
+        )
+        .arg(
+            clap::Arg::with_name("strict")
+                .long("strict")
+                .help("refuses to start if any directory under the repo root can't be opened"),
+        )
+        .arg(
+            clap::Arg::with_name("log-format")
+                .long("log-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["text", "json"])
+                .default_value(DEFAULT_LOG_FORMAT)
+                .help("format of the log output, json includes a record per request"),
+        )
+        .arg(
+            clap::Arg::with_name("otlp-endpoint")
+                .long("otlp-endpoint")
+                .takes_value(true)
+                .value_name("URL")
+                .help(
+                    "OTLP/HTTP endpoint to export traces to, ie. http://localhost:4318/v1/traces",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("max-blob-size")
+                .long("max-blob-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("most bytes of a file read by a single request, larger files have to be read in ranges"),
+        )
+        .arg(
+            clap::Arg::with_name("shutdown-timeout")
+                .long("shutdown-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .default_value(DEFAULT_SHUTDOWN_TIMEOUT)
+                .help("seconds to wait for in-flight requests to finish when stopping"),
+        )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use actix_web::{test, App};
+    use git::git2::{Buf, IndexEntry, IndexTime, ObjectType, Repository, Signature, Time};
+    use std::fs;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
     use std::str;
 
     fn start_test_server() -> test::TestServer {
-        test::start_with(test::config().h1(), || {
-            let addr = GitRepos::new(git::load_repos(Path::new("test"))).start();
+        start_test_server_with_options(TestServerOptions::default())
+    }
+
+    fn start_test_server_with(repo_root: PathBuf, repo_depth: usize) -> test::TestServer {
+        start_test_server_with_options(TestServerOptions {
+            repo_root,
+            repo_depth,
+            ..TestServerOptions::default()
+        })
+    }
+
+    // How a test server is started, by default serving the `test` repos read-only.
+    struct TestServerOptions {
+        repo_root: PathBuf,
+        repo_depth: usize,
+        max_blob_size: Option<u64>,
+        allow_writes: bool,
+        keyring: git::Keyring,
+        require_signed: bool,
+        signing_key: Option<git::SigningKey>,
+    }
+
+    impl Default for TestServerOptions {
+        fn default() -> Self {
+            TestServerOptions {
+                repo_root: PathBuf::from("test"),
+                repo_depth: 1,
+                max_blob_size: None,
+                allow_writes: false,
+                keyring: git::Keyring::default(),
+                require_signed: false,
+                signing_key: None,
+            }
+        }
+    }
+
+    fn start_test_server_with_options(options: TestServerOptions) -> test::TestServer {
+        let TestServerOptions {
+            repo_root,
+            repo_depth,
+            max_blob_size,
+            allow_writes,
+            keyring,
+            require_signed,
+            signing_key,
+        } = options;
+
+        test::start_with(test::config().h1(), move || {
+            let loaded =
+                git::load_repos(&repo_root, &[], repo_depth).expect("can't load test repos");
+            let repo_reports = loaded.diagnostics.iter().map(RepoReport::from).collect();
+            let verify_signatures = !keyring.is_empty();
+            let mut git_repos = GitRepos::new(loaded.repos).with_keyring(keyring.clone());
+            if let Some(signing_key) = &signing_key {
+                git_repos = git_repos.with_signing_key(signing_key.clone());
+            }
+            let addr = git_repos.start();
 
             App::new()
-                .data(AppState { git_repos: addr })
+                .data(AppState {
+                    git_repos: addr,
+                    repo_reports: Arc::new(repo_reports),
+                    max_blob_size,
+                    allow_writes,
+                    verify_signatures,
+                    require_signed,
+                    schema_mappings: Arc::default(),
+                })
                 .service(cat_file)
                 .service(ls_dir)
                 .service(resolve_ref)
+                .service(merged)
+                .service(batch_cat)
+                .service(archive_dir)
+                .service(blame_file)
+                .service(list_refs)
+                .service(create_tag)
+                .service(create_branch)
+                .service(move_branch)
+                .service(delete_branch)
+                .service(delete_tag)
+                .service(admin::repos)
         })
     }
 
     macro_rules! assert_test_server_responds_with {
         ($path:expr, $expected_status:expr, $expected_body:expr) => {{
             let srv = start_test_server();
+            assert_test_server_responds_with!(srv, $path, $expected_status, $expected_body)
+        }};
+        ($srv:expr, $path:expr, $expected_status:expr, $expected_body:expr) => {{
+            let srv = $srv;
 
             let req = srv.get(&$path);
             let mut resp = req.send().await.unwrap();
@@ -417,4 +2148,1765 @@ mod tests {
             "revspec 'idonot/exist' not found; class=Reference (4); code=NotFound (-3)"
         )
     }
+
+    // The modes of the entries `add_test_repo` commits.
+    const FILE_MODE: u32 = 0o100_644;
+    const SYMLINK_MODE: u32 = 0o120_000;
+    const SUBMODULE_MODE: u32 = 0o160_000;
+
+    // Creates a repo root holding a single repo at `repo_path` with the given files committed to
+    // it, returning it along with the SHA of the commit.
+    fn test_repo_root(repo_path: &str, files: &[(&str, &str)]) -> (tempfile::TempDir, String) {
+        let entries: Vec<_> = files
+            .iter()
+            .map(|(file, contents)| (*file, *contents, FILE_MODE))
+            .collect();
+        test_repo_root_with_modes(repo_path, &entries)
+    }
+
+    // Like `test_repo_root`, with the mode of each entry. Symlinks hold the path they link to and
+    // submodules the SHA of the commit they are pinned to, neither being written to the workdir.
+    fn test_repo_root_with_modes(
+        repo_path: &str,
+        entries: &[(&str, &str, u32)],
+    ) -> (tempfile::TempDir, String) {
+        let root = tempfile::Builder::new()
+            .prefix("testgitrepos")
+            .tempdir()
+            .expect("can't create tmp dir");
+        let commit_sha = add_test_repo(root.path(), repo_path, entries);
+
+        (root, commit_sha)
+    }
+
+    // Creates a repo at `repo_path` under `root` with the entries committed to it, returning the
+    // SHA of the commit.
+    fn add_test_repo(root: &Path, repo_path: &str, entries: &[(&str, &str, u32)]) -> String {
+        let repo = Repository::init(root.join(repo_path)).unwrap();
+        let mut index = repo.index().unwrap();
+
+        for (file, contents, mode) in entries {
+            if *mode == FILE_MODE {
+                let path = repo.workdir().unwrap().join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, contents).unwrap();
+                index.add_path(Path::new(file)).unwrap();
+                continue;
+            }
+
+            let id = if *mode == SUBMODULE_MODE {
+                contents.parse().unwrap()
+            } else {
+                repo.blob(contents.as_bytes()).unwrap()
+            };
+            index
+                .add(&IndexEntry {
+                    ctime: IndexTime::new(0, 0),
+                    mtime: IndexTime::new(0, 0),
+                    dev: 0,
+                    ino: 0,
+                    mode: *mode,
+                    uid: 0,
+                    gid: 0,
+                    file_size: 0,
+                    id,
+                    flags: 0,
+                    flags_extended: 0,
+                    path: file.as_bytes().to_vec(),
+                })
+                .unwrap();
+        }
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::new(
+            "Foo McBarson",
+            "foo.mcbarson@iamarealboy.net",
+            &Time::new(0, 0),
+        )
+        .unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])
+            .unwrap();
+
+        commit.to_string()
+    }
+
+    // format tests
+
+    // The repo most tests read from, holding files of every format, layers to merge, a template and
+    // the values to render it with, files mapped to schemas and a directory to archive.
+    fn structured_repo_root() -> (tempfile::TempDir, String) {
+        test_repo_root(
+            "configs",
+            &[
+                (
+                    "app.yaml",
+                    "name: app\ndatabase:\n  host: db\n  port: 5432\n",
+                ),
+                ("app.ini", "name = app\n[database]\nhost = db\n"),
+                ("broken.json", "{\"name\":"),
+                ("README", "name: app\n"),
+                (
+                    "base.yaml",
+                    "name: app\ndatabase:\n  host: db\n  port: 5432\n",
+                ),
+                ("env/prod.json", "{\"database\":{\"host\":\"prod-db\"}}"),
+                ("region/eu.toml", "region = \"eu\"\n"),
+                (
+                    "templates/app.yaml",
+                    "name: {{ name }}\nhost: {{ database.host }}\nenv: {{ env }}\n",
+                ),
+                ("values/base.yaml", "name: app\ndatabase:\n  host: db\n"),
+                ("values/prod.json", "{\"database\":{\"host\":\"prod-db\"}}"),
+                (
+                    ".gitkv/schemas.yaml",
+                    "\"services/*.yaml\": schemas/service.json\n",
+                ),
+                (
+                    "schemas/service.json",
+                    r#"{"type":"object","properties":{"port":{"type":"integer"}},"required":["port"]}"#,
+                ),
+                ("services/valid.yaml", "port: 8080\n"),
+                ("services/invalid.yaml", "port: eighty\n"),
+                ("other.yaml", "port: eighty\n"),
+                ("config/app.yaml", "name: app\n"),
+                ("config/env/prod.yaml", "env: prod\n"),
+            ],
+        )
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_converts_yaml_to_json() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/app.yaml?reference=master&format=json",
+            200,
+            "{\n  \"database\": {\n    \"host\": \"db\",\n    \"port\": 5432\n  },\n  \"name\": \"app\"\n}"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_converts_ini_to_toml() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/app.ini?reference=master&format=toml",
+            200,
+            "name = \"app\"\n\n[database]\nhost = \"db\"\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_invalid_document() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/broken.json?reference=master&format=yaml",
+            422,
+            "Invalid json document: EOF while parsing a value at line 1 column 8"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_unknown_source_format() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/README?reference=master&format=json",
+            400,
+            "Can't tell the format of 'README' from its extension"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_unknown_format() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .get("/repos/configs/cat/app.yaml?reference=master&format=xml")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_pointer() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/app.yaml?reference=master&pointer=/database/host",
+            200,
+            "\"db\""
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_pointer_and_format() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/app.yaml?reference=master&pointer=/database&format=toml",
+            200,
+            "host = \"db\"\nport = 5432\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_missing_pointer() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/app.yaml?reference=master&pointer=/database/user",
+            404,
+            "No value at '/database/user' in 'app.yaml'"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_invalid_pointer() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/app.yaml?reference=master&pointer=database",
+            400,
+            "Invalid pointer 'database', it must start with '/'"
+        );
+    }
+
+    // merged tests
+
+    #[actix_rt::test]
+    async fn merged_overrides_in_order() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/merged?reference=master&paths=base.yaml,env/prod.json,region/eu.toml&format=yaml",
+            200,
+            "---\ndatabase:\n  host: prod-db\n  port: 5432\nname: app\nregion: eu\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn merged_with_annotations() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let mut resp = srv
+            .get("/repos/configs/merged?reference=master&paths=base.yaml,env/prod.json&annotate=true")
+            .send()
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&resp.body().await.unwrap()).unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "value": {"name": "app", "database": {"host": "prod-db", "port": 5432}},
+                "sources": {
+                    "/database/host": "env/prod.json",
+                    "/database/port": "base.yaml",
+                    "/name": "base.yaml"
+                }
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn merged_with_missing_file() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/merged?reference=master&paths=base.yaml,env/dev.json",
+            404,
+            "the path 'dev.json' does not exist in the given tree; class=Tree (14); code=NotFound (-3)"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn merged_with_unstructured_file() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/merged?reference=master&paths=base.yaml,README",
+            400,
+            "Can't tell the format of 'README' from its extension"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn merged_larger_than_max_blob_size() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            max_blob_size: Some(5),
+            ..TestServerOptions::default()
+        });
+        let resp = srv
+            .get("/repos/configs/merged?reference=master&paths=base.yaml,env/prod.json")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    // render tests
+
+    #[actix_rt::test]
+    async fn cat_file_renders_template() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/templates/app.yaml?reference=master&render=true&context=values/base.yaml,values/prod.json&var.env=prod",
+            200,
+            "name: app\nhost: prod-db\nenv: prod\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_renders_template_as_another_format() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/templates/app.yaml?reference=master&render=true&context=values/base.yaml&var.env=dev&pointer=/host",
+            200,
+            "\"db\""
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_renders_template_with_missing_variable() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .get("/repos/configs/cat/templates/app.yaml?reference=master&render=true&var.env=prod")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 422);
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_renders_template_larger_than_max_blob_size() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            max_blob_size: Some(20),
+            ..TestServerOptions::default()
+        });
+        let resp = srv
+            .get(
+                "/repos/configs/cat/templates/app.yaml?reference=master&render=true&context=values/base.yaml",
+            )
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    // schema tests
+
+    async fn schema_validation(srv: &test::TestServer, path: &str) -> Option<String> {
+        let resp = srv.get(path).send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+
+        resp.headers()
+            .get(schemas::SCHEMA_VALIDATION_HEADER)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_reports_valid_file() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            schema_validation(
+                &srv,
+                "/repos/configs/cat/services/valid.yaml?reference=master"
+            )
+            .await,
+            Some("valid".to_string())
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_reports_invalid_file() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            schema_validation(
+                &srv,
+                "/repos/configs/cat/services/invalid.yaml?reference=master&format=json"
+            )
+            .await,
+            Some("invalid".to_string())
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_without_schema() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            schema_validation(&srv, "/repos/configs/cat/other.yaml?reference=master").await,
+            None
+        );
+    }
+
+    async fn range_schema_validation(srv: &test::TestServer, path: &str) -> Option<String> {
+        let resp = srv
+            .get(path)
+            .header(http::header::RANGE, "bytes=0-3")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 206);
+
+        resp.headers()
+            .get(schemas::SCHEMA_VALIDATION_HEADER)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_range_skips_validation() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            range_schema_validation(
+                &srv,
+                "/repos/configs/cat/services/invalid.yaml?reference=master"
+            )
+            .await,
+            Some("skipped".to_string())
+        );
+        assert_eq!(
+            range_schema_validation(&srv, "/repos/configs/cat/other.yaml?reference=master").await,
+            None
+        );
+    }
+
+    // range tests
+
+    async fn get_range(
+        srv: &test::TestServer,
+        path: &str,
+        range: &str,
+    ) -> (http::StatusCode, Option<String>, String) {
+        let mut resp = srv
+            .get(path)
+            .header(http::header::RANGE, range)
+            .send()
+            .await
+            .unwrap();
+        let bytes = resp.body().await.unwrap();
+        let content_range = resp
+            .headers()
+            .get(http::header::CONTENT_RANGE)
+            .map(|value| value.to_str().unwrap().to_string());
+
+        (
+            resp.status(),
+            content_range,
+            str::from_utf8(&bytes).unwrap().to_string(),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_range() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let path = "/repos/configs/cat/app.yaml?reference=master";
+
+        assert_eq!(
+            get_range(&srv, path, "bytes=0-3").await,
+            (
+                http::StatusCode::PARTIAL_CONTENT,
+                Some("bytes 0-3/44".to_string()),
+                "name".to_string()
+            )
+        );
+        assert_eq!(
+            get_range(&srv, path, "bytes=-5").await,
+            (
+                http::StatusCode::PARTIAL_CONTENT,
+                Some("bytes 39-43/44".to_string()),
+                "5432\n".to_string()
+            )
+        );
+        assert_eq!(
+            get_range(&srv, path, "bytes=44-").await,
+            (
+                http::StatusCode::RANGE_NOT_SATISFIABLE,
+                Some("bytes */44".to_string()),
+                "The range is outside of the 44 bytes of the file".to_string()
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_several_ranges() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            get_range(
+                &srv,
+                "/repos/configs/cat/app.yaml?reference=master",
+                "bytes=0-3,5-6"
+            )
+            .await,
+            (
+                http::StatusCode::OK,
+                None,
+                "name: app\ndatabase:\n  host: db\n  port: 5432\n".to_string()
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_larger_than_max_blob_size() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            max_blob_size: Some(10),
+            ..TestServerOptions::default()
+        });
+        let path = "/repos/configs/cat/app.yaml?reference=master";
+
+        let resp = srv.get(path).send().await.unwrap();
+        assert_eq!(resp.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        assert_eq!(
+            get_range(&srv, path, "bytes=0-9").await,
+            (
+                http::StatusCode::PARTIAL_CONTENT,
+                Some("bytes 0-9/44".to_string()),
+                "name: app\n".to_string()
+            )
+        );
+    }
+
+    // LFS tests
+
+    const LFS_OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    fn lfs_pointer(oid: &str) -> String {
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 10\n",
+            oid
+        )
+    }
+
+    // Creates a repo with a pointer to an object in its LFS store and one to a missing object.
+    fn lfs_repo_root() -> (tempfile::TempDir, String) {
+        let missing_oid = LFS_OID.replace('4', "5");
+        let (root, commit_sha) = test_repo_root(
+            "data",
+            &[
+                ("present.bin", &lfs_pointer(LFS_OID)),
+                ("missing.bin", &lfs_pointer(&missing_oid)),
+            ],
+        );
+
+        let objects = root.path().join("data/.git/lfs/objects/4d/7a");
+        fs::create_dir_all(&objects).unwrap();
+        fs::write(objects.join(LFS_OID), "lfs object").unwrap();
+
+        (root, commit_sha)
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_resolves_lfs_pointer() {
+        let (root, _) = lfs_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/data/cat/present.bin?reference=master",
+            200,
+            "lfs object"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_streams_lfs_object_with_its_length() {
+        let (root, _) = lfs_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .get("/repos/data/cat/present.bin?reference=master")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get(http::header::CONTENT_LENGTH).unwrap(),
+            "10"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_lfs_object_larger_than_max_blob_size() {
+        let (root, _) = lfs_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with_options(TestServerOptions {
+                repo_root: root.path().to_path_buf(),
+                max_blob_size: Some(5),
+                ..TestServerOptions::default()
+            }),
+            "/repos/data/cat/present.bin?reference=master",
+            413,
+            "'present.bin' is 10 bytes, larger than the maximum of 5, read it in ranges instead"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_range_of_lfs_object() {
+        let (root, _) = lfs_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            get_range(
+                &srv,
+                "/repos/data/cat/present.bin?reference=master",
+                "bytes=4-"
+            )
+            .await,
+            (
+                http::StatusCode::PARTIAL_CONTENT,
+                Some("bytes 4-9/10".to_string()),
+                "object".to_string()
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_serves_raw_lfs_pointer() {
+        let (root, _) = lfs_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/data/cat/present.bin?reference=master&raw=true",
+            200,
+            lfs_pointer(LFS_OID)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_missing_lfs_object() {
+        let (root, _) = lfs_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/data/cat/missing.bin?reference=master",
+            404,
+            format!(
+                "The LFS object {} is not present in the repository, read with raw=true to get its pointer",
+                LFS_OID.replace('4', "5")
+            )
+        );
+    }
+
+    // submodule tests
+
+    const LIB_URL: &str = "https://example.com/org/lib.git";
+
+    // Creates a repo `app` with the repo `lib` as a submodule at `vendor/lib`, pinned to the first
+    // of the two commits of `lib`.
+    fn submodule_repo_root(lib_url: &str) -> (tempfile::TempDir, String) {
+        let (root, pinned) = test_repo_root("lib", &[("lib.yaml", "version: 1\n")]);
+        let lib = Repository::open(root.path().join("lib")).unwrap();
+        lib.remote("origin", LIB_URL).unwrap();
+        fs::write(root.path().join("lib/lib.yaml"), "version: 2\n").unwrap();
+        let mut index = lib.index().unwrap();
+        index.add_path(Path::new("lib.yaml")).unwrap();
+        let tree = lib.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::new("Foo McBarson", "foo@example.com", &Time::new(0, 0)).unwrap();
+        let parent = lib.find_commit(pinned.parse().unwrap()).unwrap();
+        lib.commit(Some("HEAD"), &sig, &sig, "Bump", &tree, &[&parent])
+            .unwrap();
+
+        let gitmodules = format!(
+            "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = {}\n",
+            lib_url
+        );
+        let commit_sha = add_test_repo(
+            root.path(),
+            "app",
+            &[
+                (".gitmodules", &gitmodules, FILE_MODE),
+                ("vendor/lib", &pinned, SUBMODULE_MODE),
+            ],
+        );
+
+        (root, commit_sha)
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_in_submodule() {
+        let (root, _) = submodule_repo_root(LIB_URL);
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/app/cat/vendor/lib/lib.yaml?reference=master&submodules=true",
+            200,
+            "version: 1\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn ls_dir_of_submodule() {
+        let (root, _) = submodule_repo_root(LIB_URL);
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/app/ls/vendor/lib?reference=master&submodules=true",
+            200,
+            "[\"lib.yaml\"]"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_in_submodule_without_descending() {
+        let (root, _) = submodule_repo_root(LIB_URL);
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .get("/repos/app/cat/vendor/lib/lib.yaml?reference=master")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_in_submodule_not_served() {
+        let (root, _) = submodule_repo_root("https://example.com/org/other.git");
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/app/cat/vendor/lib/lib.yaml?reference=master&submodules=true",
+            404,
+            "No repo found for the submodule at 'vendor/lib' (https://example.com/org/other.git)"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_in_submodule_requiring_signed_commits() {
+        let (root, _) = submodule_repo_root(LIB_URL);
+        let (_, keyring) = ssh_keyring(root.path());
+        assert_test_server_responds_with!(
+            start_test_server_with_options(TestServerOptions {
+                repo_root: root.path().to_path_buf(),
+                keyring,
+                require_signed: true,
+                ..TestServerOptions::default()
+            }),
+            "/repos/app/cat/vendor/lib/lib.yaml?reference=master&submodules=true",
+            403,
+            "Submodules aren't served when only signed commits are, as the commits they are pinned to aren't checked"
+        );
+    }
+
+    #[test]
+    fn test_parse_submodule_mapping() {
+        assert_eq!(
+            parse_submodule_mapping("https://example.com/lib?a=b=lib"),
+            Some(("https://example.com/lib?a=b".to_string(), "lib".to_string()))
+        );
+        assert_eq!(parse_submodule_mapping("lib"), None);
+        assert_eq!(parse_submodule_mapping("https://example.com/lib="), None);
+    }
+
+    // symlink tests
+
+    fn symlink_repo_root() -> (tempfile::TempDir, String) {
+        test_repo_root_with_modes(
+            "configs",
+            &[
+                ("envs/prod-eu.yaml", "name: prod-eu\n", FILE_MODE),
+                ("envs/prod.yaml", "prod-eu.yaml", SYMLINK_MODE),
+                ("envs/passwd", "../../etc/passwd", SYMLINK_MODE),
+                ("current", "envs", SYMLINK_MODE),
+            ],
+        )
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_following_symlinks() {
+        let (root, _) = symlink_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/current/prod.yaml?reference=master&follow_symlinks=true",
+            200,
+            "name: prod-eu\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_without_following_symlinks() {
+        let (root, _) = symlink_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/envs/prod.yaml?reference=master",
+            200,
+            "prod-eu.yaml"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_following_symlink_out_of_the_tree() {
+        let (root, _) = symlink_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/envs/passwd?reference=master&follow_symlinks=true",
+            404,
+            "'envs/passwd' leads out of the tree; class=Tree (14); code=NotFound (-3)"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn ls_dir_following_symlinks() {
+        let (root, _) = symlink_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/ls/current?reference=master&follow_symlinks=true",
+            200,
+            "[\"passwd\",\"prod-eu.yaml\",\"prod.yaml\"]"
+        );
+    }
+
+    // batch cat tests
+
+    #[actix_rt::test]
+    async fn batch_cat_reads_every_file() {
+        let (root, commit_sha) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let mut resp = srv
+            .post("/repos/configs/batch-cat")
+            .send_json(&serde_json::json!({
+                "reference": "master",
+                "paths": ["app.ini", "app.yaml"]
+            }))
+            .await
+            .unwrap();
+        let bytes = resp.body().await.unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&bytes).unwrap(),
+            serde_json::json!({
+                "commit": commit_sha,
+                "files": [
+                    {"path": "app.ini", "content": base64::encode("name = app\n[database]\nhost = db\n")},
+                    {"path": "app.yaml", "content": base64::encode("name: app\ndatabase:\n  host: db\n  port: 5432\n")},
+                ]
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn batch_cat_with_missing_file() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .post("/repos/configs/batch-cat")
+            .send_json(
+                &serde_json::json!({"reference": "master", "paths": ["app.yaml", "nope.yaml"]}),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_rt::test]
+    async fn batch_cat_without_paths() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .post("/repos/configs/batch-cat")
+            .send_json(&serde_json::json!({"paths": []}))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_rt::test]
+    async fn batch_cat_larger_than_max_blob_size() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            max_blob_size: Some(32),
+            ..TestServerOptions::default()
+        });
+        let mut resp = srv
+            .post("/repos/configs/batch-cat")
+            .send_json(
+                &serde_json::json!({"reference": "master", "paths": ["app.ini", "app.yaml"]}),
+            )
+            .await
+            .unwrap();
+        let bytes = resp.body().await.unwrap();
+
+        assert_eq!(resp.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            bytes,
+            "'app.yaml' is 44 bytes, larger than the maximum of 32, read it in ranges instead"
+        );
+    }
+
+    // commit header tests
+
+    async fn commit_header(srv: &test::TestServer, path: &str) -> String {
+        let resp = srv.get(path).send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+
+        resp.headers()
+            .get(COMMIT_HEADER)
+            .expect("should have a commit header")
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_tells_the_resolved_commit() {
+        let (root, commit_sha) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            commit_header(&srv, "/repos/configs/cat/app.yaml?reference=master").await,
+            commit_sha
+        );
+        assert_eq!(
+            commit_header(
+                &srv,
+                &format!("/repos/configs/cat/app.yaml?reference={}", commit_sha)
+            )
+            .await,
+            commit_sha
+        );
+    }
+
+    #[actix_rt::test]
+    async fn ls_dir_tells_the_resolved_commit() {
+        let (root, commit_sha) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            commit_header(&srv, "/repos/configs/ls/config?reference=master").await,
+            commit_sha
+        );
+    }
+
+    #[actix_rt::test]
+    async fn merged_tells_the_resolved_commit() {
+        let (root, commit_sha) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_eq!(
+            commit_header(
+                &srv,
+                "/repos/configs/merged?reference=master&paths=app.yaml,app.ini"
+            )
+            .await,
+            commit_sha
+        );
+    }
+
+    // refs tests
+
+    fn tagged_repo_root() -> (tempfile::TempDir, String) {
+        let (root, commit_sha) = structured_repo_root();
+        let repo = Repository::open(root.path().join("configs")).unwrap();
+        let commit = repo.find_object(commit_sha.parse().unwrap(), None).unwrap();
+        let sig = Signature::new("Foo McBarson", "foo@example.com", &Time::new(0, 0)).unwrap();
+        repo.tag("release/1.0", &commit, &sig, "First release\n", false)
+            .unwrap();
+        repo.tag_lightweight("release/1.1", &commit, false).unwrap();
+        repo.reference("refs/heads/release/1.x", commit.id(), false, "Branch")
+            .unwrap();
+
+        (root, commit_sha)
+    }
+
+    #[actix_rt::test]
+    async fn list_refs_of_every_kind() {
+        let (root, commit_sha) = tagged_repo_root();
+        let tag_id = Repository::open(root.path().join("configs"))
+            .unwrap()
+            .refname_to_id("refs/tags/release/1.0")
+            .unwrap()
+            .to_string();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let mut resp = srv.get("/repos/configs/refs").send().await.unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.json::<Value>().await.unwrap(),
+            serde_json::json!([
+                {"name": "master", "kind": "branch", "target": commit_sha},
+                {"name": "release/1.x", "kind": "branch", "target": commit_sha},
+                {
+                    "name": "release/1.0",
+                    "kind": "tag",
+                    "target": commit_sha,
+                    "tag": {
+                        "id": tag_id,
+                        "tagger": {"name": "Foo McBarson", "email": "foo@example.com"},
+                        "timestamp": 0,
+                        "message": "First release\n",
+                    },
+                },
+                {"name": "release/1.1", "kind": "tag", "target": commit_sha},
+            ])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn list_refs_by_kind_and_prefix() {
+        let (root, commit_sha) = tagged_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let mut resp = srv
+            .get("/repos/configs/refs?kind=tag&prefix=release/1.1")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.json::<Value>().await.unwrap(),
+            serde_json::json!([{"name": "release/1.1", "kind": "tag", "target": commit_sha}])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn list_refs_with_invalid_repo() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/not-a-repo/refs",
+            404,
+            "No repo found with name 'not-a-repo'"
+        );
+    }
+
+    // write tests
+
+    #[actix_rt::test]
+    async fn create_tag_annotated() {
+        let (root, commit_sha) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            allow_writes: true,
+            ..TestServerOptions::default()
+        });
+        let mut resp = srv
+            .post("/repos/configs/tags")
+            .send_json(&serde_json::json!({
+                "name": "release/1.0",
+                "target": "master",
+                "message": "First release\n",
+                "tagger": {"name": "Foo McBarson", "email": "foo@example.com"},
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 201);
+        let tag = resp.json::<Value>().await.unwrap();
+        assert_eq!(tag["target"], commit_sha.as_str());
+        assert_eq!(tag["tag"]["message"], "First release\n");
+        assert_eq!(tag["tag"]["tagger"]["email"], "foo@example.com");
+
+        assert_test_server_responds_with!(
+            &srv,
+            "/repos/configs/resolve?reference=release/1.0",
+            200,
+            commit_sha
+        );
+    }
+
+    #[actix_rt::test]
+    async fn delete_tag_annotated() {
+        let (root, commit_sha) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            allow_writes: true,
+            ..TestServerOptions::default()
+        });
+        let mut resp = srv
+            .post("/repos/configs/tags")
+            .send_json(&serde_json::json!({
+                "name": "release/1.0",
+                "target": "master",
+                "message": "First release\n",
+                "tagger": {"name": "Foo McBarson", "email": "foo@example.com"},
+            }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 201);
+        let tag = resp.json::<Value>().await.unwrap();
+        let tag_id = tag["tag"]["id"].as_str().unwrap();
+
+        let resp = srv
+            .delete(format!(
+                "/repos/configs/tags/release/1.0?old_target={}",
+                commit_sha
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 409);
+        let resp = srv
+            .delete(format!(
+                "/repos/configs/tags/release/1.0?old_target={}",
+                tag_id
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 204);
+
+        let repo = Repository::open(root.path().join("configs")).unwrap();
+        assert!(repo.find_reference("refs/tags/release/1.0").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn create_tag_signed() {
+        let (root, commit_sha) = structured_repo_root();
+        let (key, keyring) = ssh_keyring(root.path());
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            allow_writes: true,
+            keyring,
+            require_signed: true,
+            signing_key: Some(git::SigningKey::Ssh(key)),
+            ..TestServerOptions::default()
+        });
+
+        let resp = srv
+            .post("/repos/configs/tags")
+            .send_json(&serde_json::json!({
+                "name": "v1",
+                "target": "master",
+                "message": "Release\n",
+                "tagger": {"name": "Foo McBarson", "email": "foo@example.com"},
+            }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 201);
+
+        // Lightweight tags have nowhere to hold a signature.
+        let resp = srv
+            .post("/repos/configs/tags")
+            .send_json(&serde_json::json!({"name": "v2", "target": "master"}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 400);
+
+        // The commit isn't signed itself, but the tag created by gitkv vouches for it.
+        let resp = srv
+            .get("/repos/configs/resolve?reference=v1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers().get(SIGNATURE_HEADER).unwrap(), "verified");
+        assert_test_server_responds_with!(
+            &srv,
+            "/repos/configs/cat/app.ini?reference=v1",
+            200,
+            "name = app\n[database]\nhost = db\n"
+        );
+        assert_test_server_responds_with!(
+            srv,
+            "/repos/configs/cat/app.ini?reference=master",
+            403,
+            format!(
+                "Commit {} isn't signed, only signed commits are served",
+                commit_sha
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn create_tag_that_exists() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            allow_writes: true,
+            ..TestServerOptions::default()
+        });
+        let tag = serde_json::json!({"name": "v1", "target": "master"});
+
+        let resp = srv
+            .post("/repos/configs/tags")
+            .send_json(&tag)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 201);
+        let resp = srv
+            .post("/repos/configs/tags")
+            .send_json(&tag)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 409);
+    }
+
+    #[actix_rt::test]
+    async fn create_tag_without_writes_allowed() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .post("/repos/configs/tags")
+            .send_json(&serde_json::json!({"name": "v1", "target": "master"}))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 403);
+    }
+
+    #[actix_rt::test]
+    async fn create_and_move_branch() {
+        let (root, first, second) = history_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            allow_writes: true,
+            ..TestServerOptions::default()
+        });
+
+        let resp = srv
+            .post("/repos/configs/branches")
+            .send_json(&serde_json::json!({"name": "release/1.x", "target": first}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 201);
+
+        // Someone else's view of the branch is out of date.
+        let resp = srv
+            .put("/repos/configs/branches/release/1.x")
+            .send_json(&serde_json::json!({"target": "master", "old_target": second}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 409);
+
+        let mut resp = srv
+            .put("/repos/configs/branches/release/1.x")
+            .send_json(&serde_json::json!({"target": "master", "old_target": first}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.json::<Value>().await.unwrap(),
+            serde_json::json!({"name": "release/1.x", "kind": "branch", "target": second})
+        );
+
+        // Going back isn't a fast-forward.
+        let resp = srv
+            .put("/repos/configs/branches/release/1.x")
+            .send_json(&serde_json::json!({"target": first, "old_target": second}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 409);
+    }
+
+    #[actix_rt::test]
+    async fn force_move_and_delete_protected_branch() {
+        let (root, first, second) = history_repo_root();
+        let repo = Repository::open(root.path().join("configs")).unwrap();
+        repo.branch(
+            "feature",
+            &repo.find_commit(second.parse().unwrap()).unwrap(),
+            false,
+        )
+        .unwrap();
+        repo.config()
+            .unwrap()
+            .set_multivar(git::PROTECTED_REF_KEY, "^$", "master")
+            .unwrap();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            allow_writes: true,
+            ..TestServerOptions::default()
+        });
+
+        let force_move = serde_json::json!({"target": first, "old_target": second, "force": true});
+        let resp = srv
+            .put("/repos/configs/branches/master")
+            .send_json(&force_move)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 403);
+        let resp = srv
+            .put("/repos/configs/branches/feature")
+            .send_json(&force_move)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+
+        let resp = srv
+            .delete(format!(
+                "/repos/configs/branches/master?old_target={}",
+                second
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 403);
+        let resp = srv
+            .delete(format!(
+                "/repos/configs/branches/feature?old_target={}",
+                first
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 204);
+        assert!(repo.find_reference("refs/heads/feature").is_err());
+    }
+
+    // point in time tests
+
+    // Creates the commit of `version: 2` in `app.yaml` at 2020-09-13T12:26:40Z on top of `parent`,
+    // returning its content to be written with or without a signature.
+    fn version_2_commit(repo: &Repository, parent: &str, message: &str) -> Buf {
+        fs::write(repo.workdir().unwrap().join("app.yaml"), "version: 2\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("app.yaml")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::new(
+            "Foo McBarson",
+            "foo@example.com",
+            &Time::new(1_600_000_000, 0),
+        )
+        .unwrap();
+        let parent = repo.find_commit(parent.parse().unwrap()).unwrap();
+        repo.commit_create_buffer(&sig, &sig, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    // Commits `version: 2` at 2020-09-13T12:26:40Z on top of `version: 1` at the Unix epoch.
+    fn history_repo_root() -> (tempfile::TempDir, String, String) {
+        let (root, first) = test_repo_root("configs", &[("app.yaml", "version: 1\n")]);
+        let repo = Repository::open(root.path().join("configs")).unwrap();
+        let content = version_2_commit(&repo, &first, "Bump");
+        let second = repo
+            .odb()
+            .unwrap()
+            .write(ObjectType::Commit, &content)
+            .unwrap();
+        repo.reference("refs/heads/master", second, true, "Bump")
+            .unwrap();
+
+        (root, first, second.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn resolve_ref_at_time() {
+        let (root, first, second) = history_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        assert_test_server_responds_with!(
+            &srv,
+            "/repos/configs/resolve?reference=master&at=2020-09-13T12:26:39Z",
+            200,
+            first
+        );
+        assert_test_server_responds_with!(
+            srv,
+            "/repos/configs/resolve?reference=master&at=2020-09-13T12:26:40Z",
+            200,
+            second
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_at_time() {
+        let (root, first, _) = history_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let mut resp = srv
+            .get("/repos/configs/cat/app.yaml?reference=master&at=2020-01-01T00:00:00Z")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(COMMIT_HEADER).unwrap(), first.as_str());
+        assert_eq!(resp.body().await.unwrap(), "version: 1\n");
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_at_invalid_time() {
+        let (root, _, _) = history_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/cat/app.yaml?reference=master&at=yesterday",
+            400,
+            "Invalid time 'yesterday', expected one like 2020-09-01T12:00:00Z"
+        );
+    }
+
+    // signature tests
+
+    // Generates an SSH key in the directory, returning its private key along with a keyring trusting
+    // it for `maintainer@example.com`.
+    fn ssh_keyring(dir: &Path) -> (PathBuf, git::Keyring) {
+        let key = dir.join("maintainer");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let allowed_signers = dir.join("allowed_signers");
+        let public_key = fs::read_to_string(key.with_extension("pub")).unwrap();
+        fs::write(
+            &allowed_signers,
+            format!("maintainer@example.com {}", public_key),
+        )
+        .unwrap();
+
+        let keyring = git::Keyring {
+            gpg: None,
+            ssh_allowed_signers: Some(allowed_signers),
+        };
+        (key, keyring)
+    }
+
+    // Signs `version: 2` at 2020-09-13T12:26:40Z with an SSH key on top of an unsigned `version: 1`,
+    // returning the keyring trusting that key along with the SHAs of the unsigned and signed commits.
+    fn signed_repo_root() -> (tempfile::TempDir, git::Keyring, String, String) {
+        let (root, unsigned) = test_repo_root("configs", &[("app.yaml", "version: 1\n")]);
+        let (key, keyring) = ssh_keyring(root.path());
+
+        let repo = Repository::open(root.path().join("configs")).unwrap();
+        let content = version_2_commit(&repo, &unsigned, "Signed");
+
+        let mut signer = Command::new("ssh-keygen")
+            .args(["-Y", "sign", "-n", "git", "-f"])
+            .arg(&key)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        signer.stdin.take().unwrap().write_all(&content).unwrap();
+        let signature = signer.wait_with_output().unwrap().stdout;
+        let signed = repo
+            .commit_signed(
+                content.as_str().unwrap(),
+                str::from_utf8(&signature).unwrap(),
+                None,
+            )
+            .unwrap();
+        repo.reference("refs/heads/master", signed, true, "Signed")
+            .unwrap();
+
+        (root, keyring, unsigned, signed.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn resolve_ref_tells_signature_status() {
+        let (root, keyring, unsigned, signed) = signed_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            keyring,
+            ..TestServerOptions::default()
+        });
+
+        let mut resp = srv
+            .get("/repos/configs/resolve?reference=master")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(SIGNATURE_HEADER).unwrap(), "verified");
+        assert_eq!(
+            resp.headers().get(SIGNER_HEADER).unwrap(),
+            "maintainer@example.com"
+        );
+        assert_eq!(resp.body().await.unwrap(), signed.as_str());
+
+        let resp = srv
+            .get(format!("/repos/configs/resolve?reference={}", unsigned))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(SIGNATURE_HEADER).unwrap(), "unsigned");
+        assert!(resp.headers().get(SIGNER_HEADER).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn resolve_ref_without_trusted_keys() {
+        let (root, _, _, _) = signed_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+
+        let resp = srv
+            .get("/repos/configs/resolve?reference=master")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get(SIGNATURE_HEADER).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_requiring_signed_commits() {
+        let (root, keyring, unsigned, signed) = signed_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            keyring,
+            require_signed: true,
+            ..TestServerOptions::default()
+        });
+
+        let mut resp = srv
+            .get("/repos/configs/cat/app.yaml?reference=master")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(COMMIT_HEADER).unwrap(), signed.as_str());
+        assert_eq!(resp.body().await.unwrap(), "version: 2\n");
+
+        assert_test_server_responds_with!(
+            srv,
+            &format!("/repos/configs/cat/app.yaml?reference={}", unsigned),
+            403,
+            format!(
+                "Commit {} isn't signed, only signed commits are served",
+                unsigned
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn blame_file_requiring_signed_commits_at_time() {
+        let (root, keyring, unsigned, _) = signed_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with_options(TestServerOptions {
+                repo_root: root.path().to_path_buf(),
+                keyring,
+                require_signed: true,
+                ..TestServerOptions::default()
+            }),
+            "/repos/configs/blame/app.yaml?reference=master&at=2020-01-01T00:00:00Z",
+            403,
+            format!(
+                "Commit {} isn't signed, only signed commits are served",
+                unsigned
+            )
+        );
+    }
+
+    // blame tests
+
+    #[actix_rt::test]
+    async fn blame_file_tells_the_commit_of_each_line() {
+        let (root, commit_sha) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let mut resp = srv
+            .get("/repos/configs/blame/app.yaml?reference=master")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get(COMMIT_HEADER).unwrap(),
+            commit_sha.as_str()
+        );
+        assert_eq!(
+            resp.json::<Value>().await.unwrap(),
+            serde_json::json!({
+                "commit": commit_sha,
+                "ranges": [{
+                    "start_line": 1,
+                    "end_line": 4,
+                    "commit": commit_sha,
+                    "author": {"name": "Foo McBarson", "email": "foo.mcbarson@iamarealboy.net"},
+                    "timestamp": 0,
+                }],
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn blame_file_with_invalid_path() {
+        let (root, _) = structured_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/configs/blame/not-a-file?reference=master",
+            404,
+            "the path 'not-a-file' does not exist in the given tree; class=Tree (14); code=NotFound (-3)"
+        );
+    }
+
+    // archive tests
+
+    #[actix_rt::test]
+    async fn archive_dir_as_tar() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let mut resp = srv
+            .get("/repos/configs/archive/config?reference=master&format=tar")
+            .send()
+            .await
+            .unwrap();
+        let bytes = resp.body().await.unwrap();
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers()
+                .get(http::header::CONTENT_DISPOSITION)
+                .unwrap(),
+            "attachment; filename=\"config.tar\""
+        );
+
+        let paths: Vec<String> = tar::Archive::new(bytes.as_ref())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(paths, vec!["app.yaml", "env/prod.yaml"]);
+    }
+
+    #[actix_rt::test]
+    async fn archive_dir_with_file() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .get("/repos/configs/archive/README?reference=master")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_rt::test]
+    async fn archive_dir_larger_than_max_blob_size() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with_options(TestServerOptions {
+            repo_root: root.path().to_path_buf(),
+            max_blob_size: Some(9),
+            ..TestServerOptions::default()
+        });
+        assert_test_server_responds_with!(
+            srv,
+            "/repos/configs/archive/config?reference=master",
+            413,
+            "'config/app.yaml' is 10 bytes, larger than the maximum of 9, read it in ranges instead"
+        );
+    }
+
+    #[test]
+    fn archive_disposition_with_unsafe_name() {
+        assert_eq!(
+            archive_disposition("my \"app\".tar").to_string(),
+            "attachment; filename=\"my \\\"app\\\".tar\""
+        );
+        assert_eq!(
+            archive_disposition("café\n.zip").to_string(),
+            "attachment; filename=\"caf__.zip\"; filename*=UTF-8''caf%C3%A9%0A.zip"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn archive_dir_with_invalid_format() {
+        let (root, _) = structured_repo_root();
+        let srv = start_test_server_with(root.path().to_path_buf(), 1);
+        let resp = srv
+            .get("/repos/configs/archive/config?reference=master&format=rar")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), 400);
+    }
+
+    // namespace tests
+
+    // Creates a repo root with a single repo namespaced as `team/service`, which holds a file in
+    // directories named like our endpoints.
+    fn namespaced_repo_root() -> (tempfile::TempDir, String) {
+        test_repo_root("team/service.git", &[("cat/ls/file", "namespaced\n")])
+    }
+
+    #[test]
+    fn is_routable_with_namespaced_keys() {
+        assert!(is_routable("configs"));
+        assert!(is_routable("cat"));
+        assert!(is_routable("team/service"));
+        assert!(is_routable("ls/service"));
+        assert!(!is_routable("team/cat"));
+        assert!(!is_routable("org/resolve/service"));
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_namespaced_repo() {
+        let (root, _) = namespaced_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 2),
+            "/repos/team/service/cat/cat/ls/file?reference=master",
+            200,
+            "namespaced\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cat_file_with_namespaced_repo_beyond_depth() {
+        let (root, _) = namespaced_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 1),
+            "/repos/team/service/cat/cat/ls/file?reference=master",
+            404,
+            "No repo found with name 'team/service'"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn ls_dir_with_namespaced_repo() {
+        let (root, _) = namespaced_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 2),
+            "/repos/team/service/ls/cat?reference=master",
+            200,
+            "[\"ls\"]"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn resolve_ref_with_namespaced_repo() {
+        let (root, commit_sha) = namespaced_repo_root();
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 2),
+            "/repos/team/service/resolve?reference=master",
+            200,
+            commit_sha
+        );
+    }
+
+    // admin tests
+
+    #[actix_rt::test]
+    async fn admin_repos_lists_examined_directories() {
+        let (root, _) = namespaced_repo_root();
+        let expected = format!(
+            r#"[{{"path":"{}","status":"not_a_repository"}},{{"path":"{}","status":"opened","key":"team/service","bare":false}}]"#,
+            root.path().join("team").display(),
+            root.path().join("team/service.git").display()
+        );
+
+        assert_test_server_responds_with!(
+            start_test_server_with(root.path().to_path_buf(), 2),
+            "/admin/repos",
+            200,
+            expected
+        );
+    }
 }
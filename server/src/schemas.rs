@@ -0,0 +1,246 @@
+use actix::Addr;
+use documents::Format;
+use globset::GlobBuilder;
+use handlers::{BlobContent, CatFiles, CatFilesResponse, FindFile, FindFileResponse, GitRepos};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tracing::{info_span, Instrument};
+
+/// The file of a repo mapping globs of paths to the JSON schemas the files they match must conform
+/// to, ie. `"services/*.yaml": schemas/service.json`. Schema paths are relative to the repo root.
+pub const SCHEMAS_PATH: &str = ".gitkv/schemas.yaml";
+
+/// The header telling whether a file read matches the schemas it's mapped to, `valid` or
+/// `invalid`, or `skipped` when only a range of it is read. It's left out for files no schema
+/// applies to.
+pub const SCHEMA_VALIDATION_HEADER: &str = "x-schema-validation";
+
+/// The mapping of the last commit read from each repo, if it has one, so that reading files from
+/// the same commit, ie. the one a branch is at, doesn't look it up every time.
+#[derive(Default)]
+pub struct Mappings(Mutex<HashMap<String, Mapping>>);
+
+// The mapping of a commit, by the repo it was read from.
+struct Mapping {
+    commit: String,
+    content: Option<Arc<Vec<u8>>>,
+}
+
+impl Mappings {
+    async fn find(
+        &self,
+        addr: &Addr<GitRepos>,
+        repo_key: &str,
+        commit: &str,
+        max_size: Option<u64>,
+    ) -> Result<Option<Arc<Vec<u8>>>, String> {
+        if let Some(mapping) = self.lock().get(repo_key) {
+            if mapping.commit == commit {
+                return Ok(mapping.content.clone());
+            }
+        }
+
+        let mailbox = info_span!("mailbox");
+        let mapping = addr
+            .send(FindFile {
+                repo_key: repo_key.to_string(),
+                commit: commit.to_string(),
+                path: PathBuf::from(SCHEMAS_PATH),
+                max_size,
+                span: mailbox.clone(),
+            })
+            .instrument(mailbox)
+            .await
+            .map_err(|err| err.to_string())
+            .and_then(|FindFileResponse(resp)| resp.map_err(|err| err.to_string()))?
+            .map(Arc::new);
+
+        self.lock().insert(
+            repo_key.to_string(),
+            Mapping {
+                commit: commit.to_string(),
+                content: mapping.clone(),
+            },
+        );
+        Ok(mapping)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Mapping>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Finds the schemas a file read at `commit` is mapped to in that commit, none when the commit has
+/// no mapping. Fails when the mapping is broken or larger than `max_size`.
+pub async fn find_schemas(
+    addr: &Addr<GitRepos>,
+    mappings: &Mappings,
+    repo_key: &str,
+    commit: &str,
+    path: &Path,
+    max_size: Option<u64>,
+) -> Result<Vec<PathBuf>, String> {
+    match mappings.find(addr, repo_key, commit, max_size).await? {
+        Some(mapping) => schema_paths(&mapping, path),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Validates a file read at `commit` against the schemas found for it, answering a description of
+/// every reason it's not valid otherwise, which includes the schemas being broken themselves or
+/// larger than `max_size`.
+pub async fn validate(
+    addr: &Addr<GitRepos>,
+    repo_key: &str,
+    commit: &str,
+    path: &Path,
+    max_size: Option<u64>,
+    schema_paths: Vec<PathBuf>,
+    blob: &BlobContent,
+) -> Result<(), String> {
+    let mailbox = info_span!("mailbox");
+    let schemas = addr
+        .send(CatFiles {
+            repo_key: repo_key.to_string(),
+            reference: commit.to_string(),
+            paths: schema_paths.clone(),
+            max_size,
+            span: mailbox.clone(),
+        })
+        .instrument(mailbox)
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|CatFilesResponse(resp)| resp.map_err(|err| err.to_string()))?;
+
+    let blob = match blob {
+        BlobContent::Bytes(bytes) => Cow::Borrowed(bytes),
+        file => Cow::Owned(
+            crate::read_content(file.clone())
+                .await
+                .map_err(|err| err.to_string())?,
+        ),
+    };
+
+    check(path, &blob, &schema_paths, &schemas.value)
+}
+
+// The schemas whose glob in the mapping matches `path`. Globs only match a single directory with
+// `*`, use `**` to match any number of them.
+fn schema_paths(mapping: &[u8], path: &Path) -> Result<Vec<PathBuf>, String> {
+    let describe = |err: &dyn std::fmt::Display| format!("{}: {}", SCHEMAS_PATH, err);
+
+    let mapping = documents::parse(mapping, Format::Yaml).map_err(|err| describe(&err))?;
+    let mapping = mapping
+        .as_object()
+        .ok_or_else(|| describe(&"must map globs to schema paths"))?;
+
+    let mut schema_paths = Vec::new();
+    for (glob, schema) in mapping {
+        let schema = schema
+            .as_str()
+            .ok_or_else(|| describe(&format!("the schema of '{}' isn't a path", glob)))?;
+        let matcher = GlobBuilder::new(glob)
+            .literal_separator(true)
+            .build()
+            .map_err(|err| describe(&err))?
+            .compile_matcher();
+
+        if matcher.is_match(path) {
+            schema_paths.push(PathBuf::from(schema));
+        }
+    }
+
+    Ok(schema_paths)
+}
+
+// Checks the file against every schema, schemas being JSON unless their extension says otherwise.
+fn check(
+    path: &Path,
+    blob: &[u8],
+    schema_paths: &[PathBuf],
+    schemas: &[Vec<u8>],
+) -> Result<(), String> {
+    let format = Format::from_path(path)
+        .ok_or_else(|| format!("{} is not a structured file", path.display()))?;
+    let document = documents::parse(blob, format).map_err(|err| err.to_string())?;
+
+    let violations: Vec<String> = schema_paths
+        .iter()
+        .zip(schemas)
+        .filter_map(|(schema_path, schema)| {
+            let format = Format::from_path(schema_path).unwrap_or(Format::Json);
+            documents::parse(schema, format)
+                .and_then(|schema| documents::validate(&document, &schema))
+                .err()
+                .map(|err| format!("{}: {}", schema_path.display(), err))
+        })
+        .collect();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &[u8] =
+        b"\"**/*.yaml\": schemas/any.json\n\"services/*.yaml\": schemas/service.json\n";
+
+    #[test]
+    fn test_schema_paths_matching_globs() {
+        assert_eq!(
+            schema_paths(MAPPING, Path::new("services/app.yaml")),
+            Ok(vec![
+                PathBuf::from("schemas/any.json"),
+                PathBuf::from("schemas/service.json")
+            ])
+        );
+        assert_eq!(
+            schema_paths(MAPPING, Path::new("services/eu/app.yaml")),
+            Ok(vec![PathBuf::from("schemas/any.json")])
+        );
+        assert_eq!(
+            schema_paths(MAPPING, Path::new("services/app.json")),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn test_schema_paths_with_invalid_mapping() {
+        assert!(schema_paths(b"- a list", Path::new("app.yaml")).is_err());
+        assert!(schema_paths(b"\"*.yaml\": 1", Path::new("app.yaml")).is_err());
+    }
+
+    #[test]
+    fn test_check_against_every_schema() {
+        let schema_paths = [
+            PathBuf::from("schemas/port.json"),
+            PathBuf::from("schemas/host.yaml"),
+        ];
+        let schemas = [
+            br#"{"required": ["port"]}"#.to_vec(),
+            b"required: [host]\n".to_vec(),
+        ];
+
+        assert_eq!(
+            check(
+                Path::new("app.yaml"),
+                b"port: 1\nhost: db\n",
+                &schema_paths,
+                &schemas
+            ),
+            Ok(())
+        );
+
+        let err = check(Path::new("app.yaml"), b"port: 1\n", &schema_paths, &schemas)
+            .expect_err("should be invalid");
+        assert!(err.starts_with("schemas/host.yaml: "), "{}", err);
+        assert!(!err.contains("schemas/port.json"), "{}", err);
+    }
+}
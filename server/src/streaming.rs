@@ -0,0 +1,162 @@
+use actix_web::web::{self, Bytes};
+use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::stream::{self, Stream};
+use futures::task::{self, ArcWake};
+use futures::SinkExt;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use std::task::Context;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+use tracing::{Instrument, Span};
+
+// The size of the chunks a body is sent in, and how many of them can wait for the client, so that
+// a body being written takes at most about a megabyte of memory.
+const CHUNK_SIZE: usize = 64 * 1024;
+const PENDING_CHUNKS: usize = 16;
+
+// How long a body being written waits for the client to read a chunk before giving up on it, so
+// that a client that stops reading doesn't hold a blocking thread for longer.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends content already read in chunks, sharing it rather than copying each chunk.
+pub fn chunks(content: Vec<u8>) -> impl Stream<Item = io::Result<Bytes>> + Unpin {
+    let content = Bytes::from(content);
+    let starts = (0..content.len()).step_by(CHUNK_SIZE);
+
+    stream::iter(
+        starts.map(move |start| Ok(content.slice(start..content.len().min(start + CHUNK_SIZE)))),
+    )
+}
+
+/// Sends what `write` writes on a blocking thread as the client reads it, `write` waiting while
+/// too many chunks are pending, for up to `SEND_TIMEOUT` each time. Failing to write aborts the
+/// response so that a truncated body isn't taken for a whole one.
+pub fn written<F>(span: Span, write: F) -> Receiver<io::Result<Bytes>>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(PENDING_CHUNKS);
+    let mut failures = sender.clone();
+
+    actix_rt::spawn(
+        async move {
+            let written = web::block(move || {
+                let body = BodyWriter {
+                    sender,
+                    timeout: SEND_TIMEOUT,
+                };
+                let mut writer = BufWriter::with_capacity(CHUNK_SIZE, body);
+                write(&mut writer)?;
+                writer.flush()
+            })
+            .await;
+
+            if let Err(err) = written {
+                warn!("Failed to write the response: {}", err);
+                let _ = failures.send(Err(io::Error::other(err.to_string()))).await;
+            }
+        }
+        .instrument(span),
+    );
+
+    receiver
+}
+
+// Sends what's written to the body of the response, waiting while too many chunks are pending.
+// Writes fail once the client is gone or has read nothing for `timeout`, which stops writing the
+// rest.
+struct BodyWriter {
+    sender: Sender<io::Result<Bytes>>,
+    timeout: Duration,
+}
+
+// Wakes the thread writing a body, parked until the client reads a chunk.
+struct Unparker(Thread);
+
+impl ArcWake for Unparker {
+    fn wake_by_ref(unparker: &Arc<Self>) {
+        unparker.0.unpark();
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let gone = |_| io::Error::new(io::ErrorKind::BrokenPipe, "the client is gone");
+        let deadline = Instant::now() + self.timeout;
+        let waker = task::waker(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        while self.sender.poll_ready(&mut cx).is_pending() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the client stopped reading",
+                ));
+            }
+            thread::park_timeout(deadline - now);
+        }
+        self.sender
+            .start_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(gone)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[actix_rt::test]
+    async fn test_chunks() {
+        let content = vec![1; CHUNK_SIZE * 2 + 1];
+        let lengths: Vec<usize> = chunks(content)
+            .map(|chunk| chunk.unwrap().len())
+            .collect()
+            .await;
+        assert_eq!(lengths, vec![CHUNK_SIZE, CHUNK_SIZE, 1]);
+    }
+
+    #[actix_rt::test]
+    async fn test_written() {
+        let body: Vec<io::Result<Bytes>> = written(Span::none(), |writer| {
+            writer.write_all(b"head")?;
+            writer.write_all(b" tail")
+        })
+        .collect()
+        .await;
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0].as_ref().unwrap(), "head tail");
+    }
+
+    #[actix_rt::test]
+    async fn test_written_with_failure() {
+        let body: Vec<io::Result<Bytes>> = written(Span::none(), |writer| {
+            writer.write_all(b"head")?;
+            Err(io::Error::other("gone"))
+        })
+        .collect()
+        .await;
+        assert!(body.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_body_writer_with_client_not_reading() {
+        let (sender, _receiver) = mpsc::channel(PENDING_CHUNKS);
+        let mut writer = BodyWriter {
+            sender,
+            timeout: Duration::from_millis(10),
+        };
+
+        let err = (0..=PENDING_CHUNKS + 1)
+            .find_map(|_| writer.write(b"chunk").err())
+            .expect("should time out");
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
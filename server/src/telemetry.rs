@@ -0,0 +1,43 @@
+use crate::logging::RequestIds;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use std::io;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Installs the tracing subscriber, keeping the ID of each request for the lines logged while
+/// handling it and, when given an `endpoint`, exporting every span to the OpenTelemetry collector
+/// listening there over OTLP/HTTP, ie. `http://localhost:4318/v1/traces`.
+///
+/// Spans are exported in batches from a background thread, so the returned provider must be shut
+/// down before exiting for the last ones to be flushed.
+pub fn init(endpoint: Option<&str>) -> io::Result<Option<SdkTracerProvider>> {
+    let provider = endpoint.map(tracer_provider).transpose()?;
+    let exporting = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(crate_name!())));
+
+    tracing_subscriber::registry()
+        .with(RequestIds)
+        .with(exporting)
+        .try_init()
+        .map_err(io::Error::other)?;
+
+    Ok(provider)
+}
+
+fn tracer_provider(endpoint: &str) -> io::Result<SdkTracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(io::Error::other)?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(crate_name!()).build())
+        .build();
+
+    Ok(provider)
+}
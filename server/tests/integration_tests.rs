@@ -1,4 +1,10 @@
 use assert_cmd::Command;
+use git::git2::{Repository, Signature, Time};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[test]
 fn fails_to_start_with_invalid_host() {
@@ -24,6 +30,153 @@ fn fails_to_start_with_invalid_repo_root() {
     assert.failure();
 }
 
+#[test]
+fn fails_to_start_with_invalid_shutdown_timeout() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let assert = cmd.arg("--shutdown-timeout=soon").assert();
+
+    assert.failure();
+}
+
+#[test]
+fn fails_to_start_with_invalid_max_blob_size() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let assert = cmd.arg("--max-blob-size=1MB").assert();
+
+    assert.failure();
+}
+
+#[test]
+fn fails_to_start_with_missing_repo_root() {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let assert = cmd.arg("--repo-root=idontexist").assert();
+
+    assert
+        .failure()
+        .stderr(predicates::str::contains("Failed to read repos directory"));
+}
+
+#[test]
+fn fails_to_start_in_strict_mode_with_broken_repo() {
+    let root = tempfile::tempdir().unwrap();
+    git::git2::Repository::init(root.path().join("broken")).unwrap();
+    std::fs::write(root.path().join("broken/.git/config"), "[core\n").unwrap();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    let assert = cmd
+        .arg(format!("--repo-root={}", root.path().display()))
+        .arg("--strict")
+        .assert();
+
+    assert
+        .failure()
+        .stderr(predicates::str::contains("Failed to open repos"));
+}
+
+// Polls until something is listening on the given port, giving up after a few seconds.
+fn wait_for_port(port: u16) {
+    let started = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "server did not start listening on port {}",
+            port
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// A port nothing is listening on, as told by the OS.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// A repo root holding a `configs` repo with `app.yaml` committed on master.
+fn repo_root() -> tempfile::TempDir {
+    let root = tempfile::tempdir().unwrap();
+    let repo = Repository::init(root.path().join("configs")).unwrap();
+    std::fs::write(root.path().join("configs/app.yaml"), "name: app\n").unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new("app.yaml")).unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = Signature::new("Foo McBarson", "foo@example.com", &Time::new(0, 0)).unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])
+        .unwrap();
+    root
+}
+
+#[cfg(unix)]
+#[test]
+fn drains_in_flight_requests_on_sigterm() {
+    let root = repo_root();
+    let port = free_port();
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_gitkv"))
+        .arg(format!("--repo-root={}", root.path().display()))
+        .arg("--host=127.0.0.1")
+        .arg(format!("--port={}", port))
+        .arg("--shutdown-timeout=5")
+        .spawn()
+        .unwrap();
+
+    wait_for_port(port);
+
+    // The request is in flight until the rest of its body is sent, after the SIGTERM.
+    let body = r#"{"reference":"master","paths":["app.yaml"]}"#;
+    let (first, rest) = body.split_at(10);
+    let mut in_flight = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(
+        in_flight,
+        "POST /repos/configs/batch-cat HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        first
+    )
+    .unwrap();
+    in_flight.flush().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    std::process::Command::new("kill")
+        .arg("-TERM")
+        .arg(server.id().to_string())
+        .status()
+        .unwrap();
+
+    let started = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_ok() {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "server still accepts connections after SIGTERM"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    in_flight.write_all(rest.as_bytes()).unwrap();
+    let mut response = String::new();
+    in_flight.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 200 OK"),
+        "unexpected response: {}",
+        response
+    );
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = server.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed() > Duration::from_secs(10) {
+            server.kill().unwrap();
+            panic!("server did not stop after SIGTERM");
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    assert!(status.success());
+}
+
 // FIXME: How to test with a process that never ends unless terminated?
 // #[test]
 // fn can_cat_file() {